@@ -0,0 +1,151 @@
+use {
+    crate::{
+        config::{
+            AxisTransform,
+            Curve
+        }
+    }
+};
+
+/// Maps a position from the `min..=max` range into `-1.0..=1.0`.
+pub fn normalize( position: i32, min: i32, max: i32 ) -> f64 {
+    if min == max {
+        return 0.0;
+    }
+
+    let value = (position as f64 - min as f64) / (max as f64 - min as f64) * 2.0 - 1.0;
+    value.clamp( -1.0, 1.0 )
+}
+
+/// Maps a value from the `-1.0..=1.0` range back into `min..=max`.
+pub fn denormalize( value: f64, min: i32, max: i32 ) -> i32 {
+    let value = value.clamp( -1.0, 1.0 );
+    (min as f64 + (value + 1.0) / 2.0 * (max as f64 - min as f64)).round() as i32
+}
+
+/// Maps a position from the `min..=max` range into `0.0..=1.0`.
+pub fn normalize_unit( position: i32, min: i32, max: i32 ) -> f64 {
+    (normalize( position, min, max ) + 1.0) / 2.0
+}
+
+/// Maps a value from the `0.0..=1.0` range back into `min..=max`.
+pub fn denormalize_unit( value: f64, min: i32, max: i32 ) -> i32 {
+    denormalize( value * 2.0 - 1.0, min, max )
+}
+
+fn rescale_magnitude( magnitude: f64, inner: f64, outer: f64 ) -> f64 {
+    if magnitude <= inner {
+        0.0
+    } else if magnitude >= 1.0 - outer {
+        1.0
+    } else {
+        (magnitude - inner) / (1.0 - inner - outer)
+    }
+}
+
+/// Applies an inner and an outer deadzone to a normalized value.
+pub fn deadzone( value: f64, inner: f64, outer: f64 ) -> f64 {
+    rescale_magnitude( value.abs(), inner, outer ).copysign( value )
+}
+
+/// Applies a deadzone to the distance from the center of a pair of normalized values, e.g. the X and Y axis of a stick.
+///
+/// Unlike applying a deadzone to each axis separately this doesn't snap the stick to the axes.
+pub fn radial_deadzone( x: f64, y: f64, inner: f64, outer: f64 ) -> (f64, f64) {
+    let magnitude = (x * x + y * y).sqrt();
+    if magnitude == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let scale = rescale_magnitude( magnitude, inner, outer ) / magnitude;
+    ((x * scale).clamp( -1.0, 1.0 ), (y * scale).clamp( -1.0, 1.0 ))
+}
+
+impl Curve {
+    /// Applies the response curve to a normalized value; the curve is mirrored for the negative values.
+    pub fn apply( &self, value: f64 ) -> f64 {
+        let magnitude = value.abs();
+        let magnitude = match *self {
+            Curve::Linear => return value,
+            Curve::Exponent( exponent ) => magnitude.powf( exponent ),
+            Curve::Points( ref points ) => interpolate( points, magnitude )
+        };
+
+        magnitude.copysign( value )
+    }
+}
+
+/// Linearly interpolates between the points, which must be sorted by their `x`.
+pub fn interpolate( points: &[(f64, f64)], x: f64 ) -> f64 {
+    let (first, last) = match (points.first(), points.last()) {
+        (Some( first ), Some( last )) => (first, last),
+        _ => return x
+    };
+
+    if x <= first.0 {
+        return first.1;
+    }
+
+    for window in points.windows( 2 ) {
+        let (x0, y0) = window[ 0 ];
+        let (x1, y1) = window[ 1 ];
+        if x <= x1 {
+            if x1 == x0 {
+                return y1;
+            }
+            return y0 + (x - x0) / (x1 - x0) * (y1 - y0);
+        }
+    }
+
+    last.1
+}
+
+impl AxisTransform {
+    /// Normalizes the position and applies the inversion.
+    pub fn input( &self, position: i32, (min, max): (i32, i32) ) -> f64 {
+        let value = normalize( position, min, max );
+        if self.invert {
+            -value
+        } else {
+            value
+        }
+    }
+
+    /// Applies the response curve and maps the value into the target range.
+    pub fn output( &self, value: f64, (min, max): (i32, i32) ) -> i32 {
+        denormalize( self.curve.apply( value ), min, max )
+    }
+}
+
+pub fn register_functions( engine: &mut rhai::Engine ) {
+    engine.register_fn( "normalize", normalize );
+    engine.register_fn( "denormalize", denormalize );
+    engine.register_fn( "rescale", |position: i32, from_min: i32, from_max: i32, to_min: i32, to_max: i32| {
+        denormalize( normalize( position, from_min, from_max ), to_min, to_max )
+    });
+    engine.register_fn( "invert", |position: i32, min: i32, max: i32| min + max - position );
+    engine.register_fn( "deadzone", |value: f64, inner: f64| deadzone( value, inner, 0.0 ) );
+    engine.register_fn( "deadzone", deadzone );
+    engine.register_fn( "radial_deadzone", |x: f64, y: f64, inner: f64, outer: f64| {
+        let (x, y) = radial_deadzone( x, y, inner, outer );
+        vec![ rhai::Dynamic::from( x ), rhai::Dynamic::from( y ) ]
+    });
+    engine.register_fn( "curve", |value: f64, exponent: f64| Curve::Exponent( exponent ).apply( value ) );
+    engine.register_fn( "curve", |value: f64, points: rhai::Array| {
+        let mut table = Vec::with_capacity( points.len() );
+        for point in points {
+            let point = match point.try_cast::< rhai::Array >() {
+                Some( point ) if point.len() == 2 => point,
+                _ => return Err( "curve: every point has to be an [x, y] pair".into() )
+            };
+
+            let x = point[ 0 ].as_float().map_err( |_| "curve: the points must be floats" )?;
+            let y = point[ 1 ].as_float().map_err( |_| "curve: the points must be floats" )?;
+            table.push( (x, y) );
+        }
+
+        table.sort_by( |a, b| a.0.partial_cmp( &b.0 ).unwrap_or( std::cmp::Ordering::Equal ) );
+        let result: Result< f64, Box< rhai::EvalAltResult > > = Ok( Curve::Points( table ).apply( value ) );
+        result
+    });
+}
@@ -5,7 +5,11 @@ use {
         },
         io,
         path::{
-            Path
+            Path,
+            PathBuf
+        },
+        time::{
+            Duration
         }
     },
     linux_input::{
@@ -57,13 +61,41 @@ impl DevicePreset {
 
         Some( preset )
     }
+
+    pub fn keys( self ) -> &'static [Key] {
+        use Key::*;
+        match self {
+            DevicePreset::Keyboard => &[
+                A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+                F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+                Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9,
+                Grave, LeftBrace, RightBrace,
+                Down, Left, Right, Up,
+                Backspace, Enter, Space, Tab, Escape,
+                LeftAlt, RightAlt,
+                LeftShift, RightShift,
+                LeftCtrl, RightCtrl,
+                LeftMeta, RightMeta,
+                Apostrophe, Backslash, Comma, Dot, Equal, Minus, Semicolon, Slash,
+                Delete, End, Home, Insert, PageDown, PageUp,
+                Pause, SysRq,
+                CapsLock, NumLock, ScrollLock,
+                Keypad0, Keypad1, Keypad2, Keypad3, Keypad4, Keypad5, Keypad6, Keypad7, Keypad8, Keypad9,
+                KeypadAsterisk, KeypadComma, KeypadDot, KeypadEnter, KeypadEqual, KeypadMinus, KeypadPlus, KeypadSlash
+            ],
+            DevicePreset::Mouse => &[
+                MouseLeft, MouseRight, MouseMiddle,
+                MouseExtra1, MouseExtra2, MouseExtra3, MouseExtra4, MouseExtra5
+            ]
+        }
+    }
 }
 
 fn try_into_bus_value( value: &toml::value::Value ) -> Option< Bus > {
     if let Some( value ) = value.as_str() {
         Bus::try_from_str( value )
     } else if let Some( value ) = value.as_integer() {
-        if value >= 0 && value <= 0xFFFF {
+        if (0..=0xFFFF).contains( &value ) {
             Some( Bus::Other( value as u16 ) )
         } else {
             None
@@ -73,11 +105,75 @@ fn try_into_bus_value( value: &toml::value::Value ) -> Option< Bus > {
     }
 }
 
+/// Resolves a user name, or a numeric user ID.
+fn lookup_uid( name: &str ) -> Option< u32 > {
+    match nix::unistd::User::from_name( name ) {
+        Ok( Some( user ) ) => Some( user.uid.as_raw() ),
+        _ => name.parse().ok()
+    }
+}
+
+/// Resolves a group name, or a numeric group ID.
+fn lookup_gid( name: &str ) -> Option< u32 > {
+    match nix::unistd::Group::from_name( name ) {
+        Ok( Some( group ) ) => Some( group.gid.as_raw() ),
+        _ => name.parse().ok()
+    }
+}
+
+fn try_into_uid( value: &toml::value::Value ) -> Option< u32 > {
+    if let Some( value ) = value.as_str() {
+        lookup_uid( value )
+    } else {
+        value.as_integer()?.try_into().ok()
+    }
+}
+
+fn try_into_gid( value: &toml::value::Value ) -> Option< u32 > {
+    if let Some( value ) = value.as_str() {
+        lookup_gid( value )
+    } else {
+        value.as_integer()?.try_into().ok()
+    }
+}
+
+/// Parses an ACL entry in the same format as `setfacl`, e.g. `user:alice:rw` or `group:input:r`.
+fn try_into_acl_entry( value: &toml::value::Value ) -> Option< AclEntry > {
+    let mut parts = value.as_str()?.split( ':' );
+    let kind = parts.next()?;
+    let name = parts.next()?;
+    let permissions = parts.next()?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let qualifier = match kind {
+        "user" | "u" => AclQualifier::User( lookup_uid( name )? ),
+        "group" | "g" => AclQualifier::Group( lookup_gid( name )? ),
+        _ => return None
+    };
+
+    let mut bits = 0;
+    for ch in permissions.chars() {
+        bits |= match ch {
+            'r' => ACL_READ,
+            'w' => ACL_WRITE,
+            'x' => ACL_EXECUTE,
+            '-' => 0,
+            _ => return None
+        };
+    }
+
+    Some( AclEntry { qualifier, permissions: bits } )
+}
+
 fn try_into_key_value( value: &toml::value::Value ) -> Option< Key > {
     if let Some( value ) = value.as_str() {
-        Key::try_from_str( value )
+        Key::try_from_str( value ).or_else( || {
+            crate::event::EXTRA_KEY_LIST.iter().find( |&&(name, _)| name == value ).map( |&(_, code)| Key::Other( code ) )
+        })
     } else if let Some( value ) = value.as_integer() {
-        if value >= 0 && value <= 0xFFFF {
+        if (0..=0xFFFF).contains( &value ) {
             Some( Key::Other( value as u16 ) )
         } else {
             None
@@ -89,9 +185,11 @@ fn try_into_key_value( value: &toml::value::Value ) -> Option< Key > {
 
 fn try_into_rel_value( value: &toml::value::Value ) -> Option< RelativeAxis > {
     if let Some( value ) = value.as_str() {
-        RelativeAxis::try_from_str( value )
+        RelativeAxis::try_from_str( value ).or_else( || {
+            crate::event::EXTRA_REL_LIST.iter().find( |&&(name, _)| name == value ).map( |&(_, code)| RelativeAxis::Other( code ) )
+        })
     } else if let Some( value ) = value.as_integer() {
-        if value >= 0 && value <= 0xFFFF {
+        if (0..=0xFFFF).contains( &value ) {
             Some( RelativeAxis::Other( value as u16 ) )
         } else {
             None
@@ -105,7 +203,7 @@ fn try_into_ff_value( value: &toml::value::Value ) -> Option< ForceFeedback > {
     if let Some( value ) = value.as_str() {
         ForceFeedback::try_from_str( value )
     } else if let Some( value ) = value.as_integer() {
-        if value >= 0 && value <= 0xFFFF {
+        if (0..=0xFFFF).contains( &value ) {
             Some( ForceFeedback::Other( value as u16 ) )
         } else {
             None
@@ -115,11 +213,26 @@ fn try_into_ff_value( value: &toml::value::Value ) -> Option< ForceFeedback > {
     }
 }
 
+/// Parses a code of an event kind which `linux_input` doesn't know about, e.g. a LED.
+fn try_into_code_value( value: &toml::value::Value, list: &[(&str, u16)] ) -> Option< u16 > {
+    if let Some( value ) = value.as_str() {
+        list.iter().find( |&&(name, _)| name == value ).map( |&(_, code)| code )
+    } else if let Some( value ) = value.as_integer() {
+        value.try_into().ok()
+    } else {
+        None
+    }
+}
+
 fn try_into_abs_s( string: &str ) -> Option< AbsoluteAxis > {
     if let Some( axis ) = AbsoluteAxis::try_from_str( string ) {
         return Some( axis );
     }
 
+    if let Some( &(_, code) ) = crate::event::EXTRA_ABS_LIST.iter().find( |&&(name, _)| name == string ) {
+        return Some( AbsoluteAxis::Other( code ) );
+    }
+
     if !string.is_empty() && string.bytes().all( |byte| byte.is_ascii_digit() ) {
         if let Ok( axis ) = string.parse() {
             return Some( AbsoluteAxis::Other( axis ) );
@@ -139,6 +252,44 @@ fn try_into_abs_s( string: &str ) -> Option< AbsoluteAxis > {
     None
 }
 
+/// Decides to which of the matching physical devices the force feedback effects are redirected.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ForceFeedbackPolicy {
+    /// The first connected device.
+    First,
+    /// Every connected device.
+    All,
+    /// The most recently connected device.
+    MostRecent
+}
+
+impl ForceFeedbackPolicy {
+    fn try_from_str( string: &str ) -> Option< ForceFeedbackPolicy > {
+        let policy = match string {
+            "first" => ForceFeedbackPolicy::First,
+            "all" => ForceFeedbackPolicy::All,
+            "most-recent" => ForceFeedbackPolicy::MostRecent,
+            _ => return None
+        };
+
+        Some( policy )
+    }
+}
+
+fn try_into_abs_value( value: &toml::value::Value ) -> Option< AbsoluteAxis > {
+    if let Some( value ) = value.as_str() {
+        try_into_abs_s( value )
+    } else if let Some( value ) = value.as_integer() {
+        if (0..=0xFFFF).contains( &value ) {
+            Some( AbsoluteAxis::Other( value as u16 ) )
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
 impl std::fmt::Display for DeviceKind {
     fn fmt( &self, fmt: &mut std::fmt::Formatter ) -> std::fmt::Result {
         let kind = match *self {
@@ -150,6 +301,57 @@ impl std::fmt::Display for DeviceKind {
     }
 }
 
+pub const ACL_READ: u16 = 0x04;
+pub const ACL_WRITE: u16 = 0x02;
+pub const ACL_EXECUTE: u16 = 0x01;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AclQualifier {
+    User( u32 ),
+    Group( u32 )
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct AclEntry {
+    pub qualifier: AclQualifier,
+    /// A combination of `ACL_READ`, `ACL_WRITE` and `ACL_EXECUTE`.
+    pub permissions: u16
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum EmergencyAction {
+    /// Restores everything and kills the daemon.
+    Exit,
+    /// Releases the device's exclusive grab and stops processing its events; pressing the combo again grabs it back.
+    Ungrab
+}
+
+impl EmergencyAction {
+    fn try_from_str( string: &str ) -> Option< EmergencyAction > {
+        let action = match string {
+            "exit" => EmergencyAction::Exit,
+            "ungrab" => EmergencyAction::Ungrab,
+            _ => return None
+        };
+
+        Some( action )
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum GrabFailurePolicy {
+    /// Leaves the device alone.
+    Skip,
+    /// Tries to grab the device again with an increasing delay, and leaves it alone after the given number of attempts.
+    Retry { attempts: u32 },
+    /// Handles the device without grabbing it.
+    NonExclusive
+}
+
+const DEFAULT_GRAB_RETRY_ATTEMPTS: u32 = 10;
+
+const DEFAULT_EMERGENCY_COMBO: &[Key] = &[Key::LeftAlt, Key::LeftCtrl, Key::Escape, Key::Pause];
+
 pub struct DeviceFilter {
     pub kind: Option< DeviceKind >,
     pub bus: Option< Bus >,
@@ -159,7 +361,15 @@ pub struct DeviceFilter {
     pub version: Option< u16 >,
     pub name: Option< String >,
     pub exclusive: bool,
-    pub chmod: Option< u16 >
+    pub chmod: Option< u16 >,
+    pub owner: Option< u32 >,
+    pub group: Option< u32 >,
+    /// Extra POSIX ACL entries to add to the device node.
+    pub acl: Vec< AclEntry >,
+    /// The keys which trigger the emergency action while the device is grabbed; empty if disabled.
+    pub emergency_combo: Vec< Key >,
+    pub on_emergency: EmergencyAction,
+    pub on_grab_failure: GrabFailurePolicy
 }
 
 pub struct VirtualDevice {
@@ -170,26 +380,305 @@ pub struct VirtualDevice {
     pub version: Option< u16 >,
     pub name: Option< String >,
     pub chmod: Option< u16 >,
+    pub owner: Option< u32 >,
+    pub group: Option< u32 >,
+    /// Extra POSIX ACL entries to add to the device node.
+    pub acl: Vec< AclEntry >,
     pub key_bits: Vec< Key >,
     pub rel_bits: Vec< RelativeAxis >,
     pub abs_bits: Vec< AbsoluteAxisBit >,
     pub ff_bits: Vec< ForceFeedback >,
-    pub redirect_force_feedback_to: Option< String >
+    pub scancodes: bool,
+    /// Enables the kernel's autorepeat on the device.
+    pub repeat: Option< Repeat >,
+    pub led_bits: Vec< u16 >,
+    pub switch_bits: Vec< u16 >,
+    /// The device filters to which the LEDs are mirrored; if empty then every grabbed keyboard which feeds this device.
+    pub mirror_leds_to: Vec< String >,
+    pub redirect_force_feedback_to: Vec< ForceFeedbackTarget >,
+    pub force_feedback_policy: ForceFeedbackPolicy,
+    pub on_force_feedback: Option< Script >
+}
+
+impl VirtualDevice {
+    pub fn has_key( &self, key: Key ) -> bool {
+        self.key_bits.contains( &key ) || self.preset.map( |preset| preset.keys().contains( &key ) ).unwrap_or( false )
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Repeat {
+    pub delay: Duration,
+    pub period: Duration
+}
+
+impl Default for Repeat {
+    fn default() -> Self {
+        // The same as the kernel's defaults.
+        Repeat {
+            delay: Duration::from_millis( 250 ),
+            period: Duration::from_millis( 33 )
+        }
+    }
+}
+
+/// Builds the autorepeat settings out of the `repeat`-like, `repeat-delay` and `repeat-period` keys.
+fn build_repeat( is_enabled: Option< bool >, delay: Option< Duration >, period: Option< Duration > ) -> Option< Repeat > {
+    if !is_enabled.unwrap_or( delay.is_some() || period.is_some() ) {
+        return None;
+    }
+
+    let default = Repeat::default();
+    Some( Repeat {
+        delay: delay.unwrap_or( default.delay ),
+        period: period.unwrap_or( default.period )
+    })
+}
+
+#[derive(Clone)]
+pub struct ForceFeedbackTarget {
+    pub device: String,
+    /// Scales the strength of the redirected effects.
+    pub gain: f64
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Releases the exclusive grab so that the device works as if `inputd` wasn't running.
+    Ungrab,
+    /// Forwards every event unchanged to the given device.
+    Passthrough( String )
+}
+
+#[derive(Clone)]
+pub struct ScriptLimits {
+    pub max_operations: u64,
+    pub max_call_depth: usize,
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+    pub max_map_size: usize,
+    pub time_limit: Duration
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        ScriptLimits {
+            max_operations: 100_000,
+            max_call_depth: 64,
+            max_string_size: 64 * 1024,
+            max_array_size: 4096,
+            max_map_size: 4096,
+            time_limit: Duration::from_millis( 100 )
+        }
+    }
 }
 
+const DEFAULT_ERROR_LIMIT: u32 = 10;
+const DEFAULT_ERROR_WINDOW: Duration = Duration::from_secs( 10 );
+
 pub struct Script {
     pub device: String,
-    pub code: String
+    pub code: String,
+    pub error_limit: u32,
+    pub error_window: Duration,
+    pub on_error_limit: ErrorPolicy,
+    /// Where the event on which the script failed is passed through; if not set then the event is dropped.
+    pub passthrough_to: Option< String >,
+    pub default_target: Option< String >,
+    pub passthrough: bool,
+    pub limits: ScriptLimits
+}
+
+pub struct Remap {
+    pub device: String,
+    /// For a device without scripts the first remap's target also gets all of the events which aren't remapped.
+    pub target: String,
+    pub keys: Vec< (Key, Vec< Key >) >,
+    /// If set the physical repeats of the remapped keys are dropped and generated by us instead.
+    pub generate_repeat: Option< Repeat >
+}
+
+pub struct MergeKey {
+    pub device: String,
+    pub from: Key,
+    pub to: Key
+}
+
+#[derive(Clone)]
+pub enum Curve {
+    Linear,
+    Exponent( f64 ),
+    /// A table of `(input, output)` points for the `0.0..=1.0` range, sorted by the input.
+    Points( Vec< (f64, f64) > )
+}
+
+#[derive(Clone)]
+pub struct AxisTransform {
+    pub invert: bool,
+    pub deadzone: f64,
+    pub outer_deadzone: f64,
+    /// The other axis of a stick, in which case the deadzone is applied radially.
+    pub radial_with: Option< AbsoluteAxis >,
+    pub curve: Curve
+}
+
+impl Default for AxisTransform {
+    fn default() -> Self {
+        AxisTransform {
+            invert: false,
+            deadzone: 0.0,
+            outer_deadzone: 0.0,
+            radial_with: None,
+            curve: Curve::Linear
+        }
+    }
+}
+
+pub struct MergeAxis {
+    pub device: String,
+    pub from: AbsoluteAxis,
+    pub to: AbsoluteAxis,
+    /// Overrides the range reported by the source device.
+    pub range: Option< (i32, i32) >,
+    pub transform: AxisTransform
+}
+
+/// Merged keys and axes are handled before any remaps or scripts, which never get to see them.
+pub struct Merge {
+    pub target: String,
+    pub keys: Vec< MergeKey >,
+    pub axes: Vec< MergeAxis >
+}
+
+#[derive(Clone)]
+pub enum EmulationMode {
+    /// Turns the deflection of an analog stick into pointer motion.
+    StickToMouse {
+        deadzone: f64,
+        /// The pointer speed at full deflection, in pixels per second.
+        speed: f64,
+        /// The exponent of the response curve.
+        acceleration: f64
+    },
+    /// Turns mouse motion into the deflection of an analog stick.
+    MouseToStick {
+        /// How much a single count of mouse motion deflects the stick, as a fraction of the full deflection.
+        sensitivity: f64,
+        /// How fast the stick returns to the center; it stays where it is if not set.
+        recenter: Option< Duration >
+    }
+}
+
+impl EmulationMode {
+    pub fn name( &self ) -> &'static str {
+        match *self {
+            EmulationMode::StickToMouse { .. } => "stick-to-mouse",
+            EmulationMode::MouseToStick { .. } => "mouse-to-stick"
+        }
+    }
+}
+
+pub struct Emulation {
+    pub device: String,
+    pub target: String,
+    /// The source axes for the stick-to-mouse mode, and the target axes for the mouse-to-stick mode.
+    pub x_axis: AbsoluteAxis,
+    pub y_axis: AbsoluteAxis,
+    pub tick: Duration,
+    pub mode: EmulationMode
+}
+
+#[derive(Clone)]
+pub enum Acceleration {
+    Flat,
+    /// Speeds up the pointer linearly with its speed above the threshold, similar to libinput's adaptive profile.
+    Adaptive {
+        /// The speed, in counts per millisecond, below which the pointer isn't accelerated.
+        threshold: f64,
+        /// How much the factor grows with each count per millisecond above the threshold.
+        slope: f64,
+        max_factor: f64
+    },
+    /// A table of `(speed, factor)` points, sorted by the speed.
+    Custom( Vec< (f64, f64) > )
+}
+
+#[derive(Clone)]
+pub struct PointerProfile {
+    pub sensitivity: f64,
+    pub acceleration: Acceleration
+}
+
+/// The DPI to which the motion of every mouse is normalized.
+pub const REFERENCE_DPI: u32 = 1000;
+
+pub struct Pointer {
+    pub device: String,
+    pub dpi: u32,
+    pub profile: String
+}
+
+/// Turns the mouse motion into scrolling while a button is held.
+pub struct ButtonScroll {
+    pub device: String,
+    pub target: String,
+    pub button: Key,
+    /// How far the mouse has to move before it starts scrolling; if it doesn't the button is clicked on release.
+    pub threshold: f64,
+    /// How far the mouse has to move to scroll by a single notch.
+    pub distance: f64,
+    pub horizontal: bool,
+    pub natural: bool
+}
+
+/// Enables the gesture recognition for a touchpad; the distances are relative to the size of the touchpad.
+pub struct Gestures {
+    pub device: String,
+    pub swipe_distance: f64,
+    /// How much the fingers have to spread or come together to count as a pinch.
+    pub pinch_scale: f64,
+    pub tap_time: Duration,
+    pub tap_distance: f64,
+    /// How close to the edge a finger has to land to start an edge swipe.
+    pub edge_margin: f64
+}
+
+/// A rectangle given as fractions of the whole area, as `(left, top, right, bottom)`.
+pub type Area = (f64, f64, f64, f64);
+
+/// Transforms the absolute X and Y axes of a tablet or a touchscreen.
+pub struct AbsoluteMapping {
+    pub device: String,
+    /// The clockwise rotation in degrees; either 0, 90, 180 or 270.
+    pub rotation: u16,
+    /// Rotates the device by additional 180 degrees.
+    pub left_handed: bool,
+    /// The part of the device which is used, in the device's own orientation.
+    pub area: Area,
+    /// The part of the output range to which the area is mapped, e.g. a single monitor.
+    pub region: Area,
+    /// The aspect ratio of the region, to which the area is shrunk to avoid distortion.
+    pub aspect_ratio: Option< f64 >
 }
 
 pub struct Config {
+    pub path: PathBuf,
     pub device_filters: IndexMap< String, DeviceFilter >,
     pub virtual_devices: IndexMap< String, VirtualDevice >,
     pub scripts: Vec< Script >,
+    pub remaps: Vec< Remap >,
+    pub merges: Vec< Merge >,
+    pub emulations: Vec< Emulation >,
+    pub pointer_profiles: IndexMap< String, PointerProfile >,
+    pub pointers: Vec< Pointer >,
+    pub button_scrolls: Vec< ButtonScroll >,
+    pub gestures: Vec< Gestures >,
+    pub absolute_mappings: Vec< AbsoluteMapping >
 }
 
 impl Config {
     pub fn load_from_file( path: impl AsRef< Path > ) -> Result< Self, io::Error > {
+        let path = path.as_ref();
         let data = std::fs::read_to_string( path )?;
         let doc: toml::Value = data.parse().map_err( |error| io::Error::new( io::ErrorKind::InvalidData, error ) )?;
 
@@ -213,6 +702,14 @@ impl Config {
         let mut device_filters = IndexMap::new();
         let mut virtual_devices = IndexMap::new();
         let mut scripts = Vec::new();
+        let mut remaps = Vec::new();
+        let mut merges = Vec::new();
+        let mut emulations = Vec::new();
+        let mut pointer_profiles = IndexMap::new();
+        let mut pointers = Vec::new();
+        let mut button_scrolls = Vec::new();
+        let mut gestures = Vec::new();
+        let mut absolute_mappings = Vec::new();
 
         for (toplevel_key, item) in doc.as_table().unwrap().iter() {
             match toplevel_key.as_str() {
@@ -231,6 +728,13 @@ impl Config {
                         let mut kind = None;
                         let mut exclusive = None;
                         let mut chmod = None;
+                        let mut owner = None;
+                        let mut group = None;
+                        let mut acl = Vec::new();
+                        let mut emergency_combo = None;
+                        let mut on_emergency = None;
+                        let mut on_grab_failure = None;
+                        let mut grab_retry_limit = None;
 
                         for (property_name, item) in item.iter() {
                             match property_name.as_str() {
@@ -280,6 +784,52 @@ impl Config {
                                     let item = item.try_into().ok().or_err( || format!( "\"{}.{}.{}\" is out of range", toplevel_key, nth, property_name ) )?;
                                     chmod = Some( item );
                                 },
+                                "owner" => {
+                                    let item = try_into_uid( item ).or_err( || format!( "\"{}.{}.{}\" is not a valid user: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                    owner = Some( item );
+                                },
+                                "group" => {
+                                    let item = try_into_gid( item ).or_err( || format!( "\"{}.{}.{}\" is not a valid group: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                    group = Some( item );
+                                },
+                                "acl" => {
+                                    let item = item.as_array().or_err( || format!( "\"{}.{}.{}\" is not an array", toplevel_key, nth, property_name ) )?;
+                                    for item in item.iter() {
+                                        let item = try_into_acl_entry( item ).or_err( || format!( "\"{}.{}.{}\" has an invalid entry: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                        acl.push( item );
+                                    }
+                                },
+                                "emergency-combo" => {
+                                    let mut keys = Vec::new();
+                                    if item.as_bool() != Some( false ) {
+                                        let item = item.as_array().or_err( || format!( "\"{}.{}.{}\" is neither an array nor 'false'", toplevel_key, nth, property_name ) )?;
+                                        for item in item.iter() {
+                                            let item = try_into_key_value( item ).or_err( || format!( "\"{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                            // A duplicated key would make the combo impossible to trigger.
+                                            if !keys.contains( &item ) {
+                                                keys.push( item );
+                                            }
+                                        }
+                                    }
+                                    emergency_combo = Some( keys );
+                                },
+                                "on-emergency" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = EmergencyAction::try_from_str( item ).or_err( || format!( "key \"{}.{}.{}\" has an invalid value", toplevel_key, nth, property_name ) )?;
+                                    on_emergency = Some( item );
+                                },
+                                "on-grab-failure" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    if !["skip", "retry", "non-exclusive"].contains( &item ) {
+                                        return err( format!( "key \"{}.{}.{}\" has an invalid value", toplevel_key, nth, property_name ) );
+                                    }
+                                    on_grab_failure = Some( item.to_owned() );
+                                },
+                                "grab-retry-limit" => {
+                                    let item = item.as_integer().or_err( || format!( "\"{}.{}.{}\" is not an integer", toplevel_key, nth, property_name ) )?.to_owned();
+                                    let item: u32 = item.try_into().ok().filter( |&value| value > 0 ).or_err( || format!( "\"{}.{}.{}\" is out of range", toplevel_key, nth, property_name ) )?;
+                                    grab_retry_limit = Some( item );
+                                },
                                 property_name => {
                                     return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
                                 }
@@ -294,6 +844,15 @@ impl Config {
                             return err( format!( "\"{}.{}\" is missing an 'ref'", toplevel_key, nth ) )
                         };
 
+                        let on_grab_failure = match on_grab_failure.as_deref() {
+                            Some( "retry" ) => GrabFailurePolicy::Retry { attempts: grab_retry_limit.unwrap_or( DEFAULT_GRAB_RETRY_ATTEMPTS ) },
+                            _ if grab_retry_limit.is_some() => {
+                                return err( format!( "\"{}.{}.grab-retry-limit\" requires 'on-grab-failure' to be set to 'retry'", toplevel_key, nth ) )
+                            },
+                            Some( "non-exclusive" ) => GrabFailurePolicy::NonExclusive,
+                            _ => GrabFailurePolicy::Skip
+                        };
+
                         device_filters.insert( internal_name, DeviceFilter {
                             name,
                             bus,
@@ -303,7 +862,13 @@ impl Config {
                             version,
                             kind,
                             exclusive: exclusive.unwrap_or( false ),
-                            chmod
+                            chmod,
+                            owner,
+                            group,
+                            acl,
+                            emergency_combo: emergency_combo.unwrap_or_else( || DEFAULT_EMERGENCY_COMBO.to_vec() ),
+                            on_emergency: on_emergency.unwrap_or( EmergencyAction::Exit ),
+                            on_grab_failure
                         });
                     }
                 },
@@ -319,11 +884,23 @@ impl Config {
                         let mut product = None;
                         let mut version = None;
                         let mut chmod = None;
+                        let mut owner = None;
+                        let mut group = None;
+                        let mut acl = Vec::new();
                         let mut key_bits = Vec::new();
                         let mut rel_bits = Vec::new();
                         let mut abs_bits = Vec::new();
                         let mut ff_bits = Vec::new();
-                        let mut redirect_force_feedback_to = None;
+                        let mut scancodes = None;
+                        let mut repeat = None;
+                        let mut repeat_delay = None;
+                        let mut repeat_period = None;
+                        let mut led_bits = Vec::new();
+                        let mut switch_bits = Vec::new();
+                        let mut mirror_leds_to = Vec::new();
+                        let mut redirect_force_feedback_to = Vec::new();
+                        let mut force_feedback_policy = None;
+                        let mut on_force_feedback = None;
                         for (property_name, item) in item.iter() {
                             match property_name.as_str() {
                                 "ref" => {
@@ -363,6 +940,21 @@ impl Config {
                                     let item = item.try_into().ok().or_err( || format!( "\"{}.{}.{}\" is out of range", toplevel_key, nth, property_name ) )?;
                                     chmod = Some( item );
                                 },
+                                "owner" => {
+                                    let item = try_into_uid( item ).or_err( || format!( "\"{}.{}.{}\" is not a valid user: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                    owner = Some( item );
+                                },
+                                "group" => {
+                                    let item = try_into_gid( item ).or_err( || format!( "\"{}.{}.{}\" is not a valid group: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                    group = Some( item );
+                                },
+                                "acl" => {
+                                    let item = item.as_array().or_err( || format!( "\"{}.{}.{}\" is not an array", toplevel_key, nth, property_name ) )?;
+                                    for item in item.iter() {
+                                        let item = try_into_acl_entry( item ).or_err( || format!( "\"{}.{}.{}\" has an invalid entry: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                        acl.push( item );
+                                    }
+                                },
                                 "keys" => {
                                     key_bits = Vec::new();
                                     let item = item.as_array().or_err( || format!( "\"{}.{}.{}\" is not an array", toplevel_key, nth, property_name ) )?.to_owned();
@@ -387,6 +979,52 @@ impl Config {
                                         ff_bits.push( item );
                                     }
                                 },
+                                "scancodes" => {
+                                    let item = item.as_bool().or_err( || format!( "\"{}.{}.{}\" is not a boolean", toplevel_key, nth, property_name ) )?;
+                                    scancodes = Some( item );
+                                },
+                                "repeat" => {
+                                    let item = item.as_bool().or_err( || format!( "\"{}.{}.{}\" is not a boolean", toplevel_key, nth, property_name ) )?;
+                                    repeat = Some( item );
+                                },
+                                "repeat-delay" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = humantime::parse_duration( item ).ok().or_err( || format!( "\"{}.{}.{}\" is not a valid duration", toplevel_key, nth, property_name ) )?;
+                                    repeat_delay = Some( item );
+                                },
+                                "repeat-period" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = humantime::parse_duration( item ).ok().or_err( || format!( "\"{}.{}.{}\" is not a valid duration", toplevel_key, nth, property_name ) )?;
+                                    repeat_period = Some( item );
+                                },
+                                "leds" => {
+                                    led_bits = Vec::new();
+                                    let item = item.as_array().or_err( || format!( "\"{}.{}.{}\" is not an array", toplevel_key, nth, property_name ) )?.to_owned();
+                                    for item in item.iter() {
+                                        let item = try_into_code_value( item, crate::event::LED_LIST ).or_err( || format!( "\"{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                        led_bits.push( item );
+                                    }
+                                },
+                                "switches" => {
+                                    switch_bits = Vec::new();
+                                    let item = item.as_array().or_err( || format!( "\"{}.{}.{}\" is not an array", toplevel_key, nth, property_name ) )?.to_owned();
+                                    for item in item.iter() {
+                                        let item = try_into_code_value( item, crate::event::SWITCH_LIST ).or_err( || format!( "\"{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                        switch_bits.push( item );
+                                    }
+                                },
+                                "mirror-leds-to" => {
+                                    mirror_leds_to = Vec::new();
+                                    let items = match item.as_array() {
+                                        Some( items ) => items.clone(),
+                                        None => vec![ item.clone() ]
+                                    };
+
+                                    for item in items.iter() {
+                                        let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, item ) )?.to_owned();
+                                        mirror_leds_to.push( item );
+                                    }
+                                },
                                 "abs" => {
                                     let item = item.as_table().or_err( || format!( "\"{}.{}.{}\" is not a table", toplevel_key, nth, property_name ) )?;
                                     for (axis_name, item) in item.iter() {
@@ -451,8 +1089,59 @@ impl Config {
                                     }
                                 },
                                 "redirect-force-feedback-to" => {
+                                    redirect_force_feedback_to = Vec::new();
+                                    let items = match item.as_array() {
+                                        Some( items ) => items.clone(),
+                                        None => vec![ item.clone() ]
+                                    };
+
+                                    for item in items.iter() {
+                                        let target = if let Some( device ) = item.as_str() {
+                                            ForceFeedbackTarget {
+                                                device: device.to_owned(),
+                                                gain: 1.0
+                                            }
+                                        } else if let Some( table ) = item.as_table() {
+                                            let mut device = None;
+                                            let mut gain = None;
+                                            for (subproperty_name, item) in table.iter() {
+                                                match subproperty_name.as_str() {
+                                                    "device" => {
+                                                        let item = item.as_str().or_err( || format!( "\"{}.{}.{}.{}\" is not a string", toplevel_key, nth, property_name, subproperty_name ) )?.to_owned();
+                                                        device = Some( item );
+                                                    },
+                                                    "gain" => {
+                                                        let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}.{}\" is not a number", toplevel_key, nth, property_name, subproperty_name ) )?;
+                                                        if item < 0.0 {
+                                                            return err( format!( "\"{}.{}.{}.{}\" cannot be negative", toplevel_key, nth, property_name, subproperty_name ) );
+                                                        }
+                                                        gain = Some( item );
+                                                    },
+                                                    subproperty_name => {
+                                                        return err( format!( "unrecognized key: \"{}.{}.{}.{}\"", toplevel_key, nth, property_name, subproperty_name ) )
+                                                    }
+                                                }
+                                            }
+
+                                            ForceFeedbackTarget {
+                                                device: device.or_err( || format!( "missing \"{}.{}.{}.device\"", toplevel_key, nth, property_name ) )?,
+                                                gain: gain.unwrap_or( 1.0 )
+                                            }
+                                        } else {
+                                            return err( format!( "\"{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, item ) );
+                                        };
+
+                                        redirect_force_feedback_to.push( target );
+                                    }
+                                },
+                                "force-feedback-policy" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = ForceFeedbackPolicy::try_from_str( item ).or_err( || format!( "key \"{}.{}.{}\" has an invalid value", toplevel_key, nth, property_name ) )?;
+                                    force_feedback_policy = Some( item );
+                                },
+                                "on-force-feedback" => {
                                     let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
-                                    redirect_force_feedback_to = Some( item );
+                                    on_force_feedback = Some( item );
                                 },
                                 property_name => {
                                     return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
@@ -468,6 +1157,18 @@ impl Config {
                             return err( format!( "\"{}.{}\" is missing an 'ref'", toplevel_key, nth ) )
                         };
 
+                        let on_force_feedback = on_force_feedback.map( |code| Script {
+                            device: internal_name.clone(),
+                            code,
+                            error_limit: DEFAULT_ERROR_LIMIT,
+                            error_window: DEFAULT_ERROR_WINDOW,
+                            on_error_limit: ErrorPolicy::Ungrab,
+                            passthrough_to: None,
+                            default_target: None,
+                            passthrough: false,
+                            limits: ScriptLimits::default()
+                        });
+
                         virtual_devices.insert( internal_name, VirtualDevice {
                             preset,
                             name,
@@ -476,11 +1177,21 @@ impl Config {
                             product,
                             version,
                             chmod,
+                            owner,
+                            group,
+                            acl,
                             key_bits,
                             rel_bits,
                             abs_bits,
                             ff_bits,
-                            redirect_force_feedback_to
+                            scancodes: scancodes.unwrap_or( preset == Some( DevicePreset::Keyboard ) ),
+                            repeat: build_repeat( repeat, repeat_delay, repeat_period ),
+                            led_bits,
+                            switch_bits,
+                            mirror_leds_to,
+                            redirect_force_feedback_to,
+                            force_feedback_policy: force_feedback_policy.unwrap_or( ForceFeedbackPolicy::First ),
+                            on_force_feedback
                         });
                     }
                 },
@@ -491,6 +1202,18 @@ impl Config {
 
                         let mut device = None;
                         let mut code = None;
+                        let mut error_limit = None;
+                        let mut error_window = None;
+                        let mut on_error_limit = None;
+                        let mut passthrough_to = None;
+                        let mut max_operations = None;
+                        let mut max_call_depth = None;
+                        let mut max_string_size = None;
+                        let mut max_array_size = None;
+                        let mut max_map_size = None;
+                        let mut time_limit = None;
+                        let mut default_target = None;
+                        let mut passthrough = None;
                         for (property_name, item) in item.iter() {
                             match property_name.as_str() {
                                 "device" => {
@@ -501,6 +1224,66 @@ impl Config {
                                     let item = item.as_str().or_err( || format!( "\"{}.'{}'.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
                                     code = Some( item );
                                 },
+                                "error-limit" => {
+                                    let item = item.as_integer().or_err( || format!( "\"{}.{}.{}\" is not an integer", toplevel_key, nth, property_name ) )?.to_owned();
+                                    let item = item.try_into().ok().or_err( || format!( "\"{}.{}.{}\" is out of range", toplevel_key, nth, property_name ) )?;
+                                    error_limit = Some( item );
+                                },
+                                "error-window" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = humantime::parse_duration( item ).ok().or_err( || format!( "\"{}.{}.{}\" is not a valid duration", toplevel_key, nth, property_name ) )?;
+                                    error_window = Some( item );
+                                },
+                                "on-error-limit" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    match item.as_str() {
+                                        "ungrab" | "passthrough" => {},
+                                        _ => return err( format!( "key \"{}.{}.{}\" has an invalid value", toplevel_key, nth, property_name ) )
+                                    }
+                                    on_error_limit = Some( item );
+                                },
+                                "passthrough-to" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    passthrough_to = Some( item );
+                                },
+                                "max-operations" => {
+                                    let item = item.as_integer().or_err( || format!( "\"{}.{}.{}\" is not an integer", toplevel_key, nth, property_name ) )?.to_owned();
+                                    let item = item.try_into().ok().or_err( || format!( "\"{}.{}.{}\" is out of range", toplevel_key, nth, property_name ) )?;
+                                    max_operations = Some( item );
+                                },
+                                "max-call-depth" => {
+                                    let item = item.as_integer().or_err( || format!( "\"{}.{}.{}\" is not an integer", toplevel_key, nth, property_name ) )?.to_owned();
+                                    let item = item.try_into().ok().or_err( || format!( "\"{}.{}.{}\" is out of range", toplevel_key, nth, property_name ) )?;
+                                    max_call_depth = Some( item );
+                                },
+                                "max-string-size" => {
+                                    let item = item.as_integer().or_err( || format!( "\"{}.{}.{}\" is not an integer", toplevel_key, nth, property_name ) )?.to_owned();
+                                    let item = item.try_into().ok().or_err( || format!( "\"{}.{}.{}\" is out of range", toplevel_key, nth, property_name ) )?;
+                                    max_string_size = Some( item );
+                                },
+                                "max-array-size" => {
+                                    let item = item.as_integer().or_err( || format!( "\"{}.{}.{}\" is not an integer", toplevel_key, nth, property_name ) )?.to_owned();
+                                    let item = item.try_into().ok().or_err( || format!( "\"{}.{}.{}\" is out of range", toplevel_key, nth, property_name ) )?;
+                                    max_array_size = Some( item );
+                                },
+                                "max-map-size" => {
+                                    let item = item.as_integer().or_err( || format!( "\"{}.{}.{}\" is not an integer", toplevel_key, nth, property_name ) )?.to_owned();
+                                    let item = item.try_into().ok().or_err( || format!( "\"{}.{}.{}\" is out of range", toplevel_key, nth, property_name ) )?;
+                                    max_map_size = Some( item );
+                                },
+                                "time-limit" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = humantime::parse_duration( item ).ok().or_err( || format!( "\"{}.{}.{}\" is not a valid duration", toplevel_key, nth, property_name ) )?;
+                                    time_limit = Some( item );
+                                },
+                                "default-target" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    default_target = Some( item );
+                                },
+                                "passthrough" => {
+                                    let item = item.as_bool().or_err( || format!( "\"{}.{}.{}\" is not a boolean", toplevel_key, nth, property_name ) )?.to_owned();
+                                    passthrough = Some( item );
+                                },
                                 property_name => {
                                     return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
                                 }
@@ -509,38 +1292,879 @@ impl Config {
 
                         let device = device.or_err( || format!( "missing \"{}.{}.device\"", toplevel_key, nth ) )?;
                         let code = code.or_err( || format!( "missing \"{}.{}.script\"", toplevel_key, nth ) )?;
+                        let passthrough = passthrough.unwrap_or( false );
+                        if passthrough && default_target.is_none() {
+                            return err( format!( "missing \"{}.{}.default-target\" (required when 'passthrough' is enabled)", toplevel_key, nth ) );
+                        }
+
+                        // The event which makes the script exceed its limits is passed through there.
+                        // The event which makes the script exceed its limits is passed through there, or dropped if there's no target.
+                        let passthrough_to = passthrough_to.or_else( || default_target.clone() );
+                        let on_error_limit = match on_error_limit.as_deref() {
+                            Some( "passthrough" ) => {
+                                let target = passthrough_to.clone().or_err( || format!( "missing \"{}.{}.passthrough-to\"", toplevel_key, nth ) )?;
+                                ErrorPolicy::Passthrough( target )
+                            },
+                            _ => ErrorPolicy::Ungrab
+                        };
+
+                        let default_limits = ScriptLimits::default();
+                        let limits = ScriptLimits {
+                            max_operations: max_operations.unwrap_or( default_limits.max_operations ),
+                            max_call_depth: max_call_depth.unwrap_or( default_limits.max_call_depth ),
+                            max_string_size: max_string_size.unwrap_or( default_limits.max_string_size ),
+                            max_array_size: max_array_size.unwrap_or( default_limits.max_array_size ),
+                            max_map_size: max_map_size.unwrap_or( default_limits.max_map_size ),
+                            time_limit: time_limit.unwrap_or( default_limits.time_limit )
+                        };
+
                         scripts.push( Script {
                             device,
-                            code
+                            code,
+                            error_limit: error_limit.unwrap_or( DEFAULT_ERROR_LIMIT ),
+                            error_window: error_window.unwrap_or( DEFAULT_ERROR_WINDOW ),
+                            on_error_limit,
+                            passthrough_to,
+                            default_target,
+                            passthrough,
+                            limits
                         })
                     }
                 },
-                toplevel_key => return err( format!( "unrecognized key: \"{}\"", toplevel_key ) )
-            }
-        }
-
-        for script in &scripts {
-            if !device_filters.contains_key( &script.device ) {
-                return err( format!( "[[script]] refers to a non-existing device filter: \"{}\"", script.device ) );
-            }
-        }
-
-        for (virtual_device_name, virtual_device) in &virtual_devices {
-            if device_filters.contains_key( virtual_device_name ) {
-                return err( format!( "same name used as a device filter and a virtual device: \"{}\"", virtual_device_name ) );
-            }
+                "remap" => {
+                    let item = item.as_array().or_err( || format!( "\"{}\" is not an array", toplevel_key ) )?;
+                    for (nth, item) in item.iter().enumerate() {
+                        let item = item.as_table().or_err( || format!( "\"{}.{}\" is not a table", toplevel_key, nth ) )?;
 
-            if let Some( ref target ) = virtual_device.redirect_force_feedback_to {
-                if !device_filters.contains_key( target ) {
-                    return err( format!( "[[virtual-device]]'s 'redirect-force-feedback-to' refers to a non-existing device filter: \"{}\"", target ) );
-                }
+                        let mut device = None;
+                        let mut target = None;
+                        let mut keys = Vec::new();
+                        let mut generate_repeat = None;
+                        let mut repeat_delay = None;
+                        let mut repeat_period = None;
+                        for (property_name, item) in item.iter() {
+                            match property_name.as_str() {
+                                "device" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    device = Some( item );
+                                },
+                                "target" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    target = Some( item );
+                                },
+                                "keys" => {
+                                    let item = item.as_array().or_err( || format!( "\"{}.{}.{}\" is not an array", toplevel_key, nth, property_name ) )?;
+                                    for (nth_key, item) in item.iter().enumerate() {
+                                        let item = item.as_table().or_err( || format!( "\"{}.{}.{}.{}\" is not a table", toplevel_key, nth, property_name, nth_key ) )?;
+                                        let mut from = None;
+                                        let mut to = Vec::new();
+                                        for (subproperty_name, item) in item.iter() {
+                                            match subproperty_name.as_str() {
+                                                "from" => {
+                                                    let item = try_into_key_value( item ).or_err( || format!( "\"{}.{}.{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, nth_key, subproperty_name, item ) )?;
+                                                    from = Some( item );
+                                                },
+                                                "to" => {
+                                                    to = Vec::new();
+                                                    if let Some( item ) = item.as_array() {
+                                                        for item in item.iter() {
+                                                            let item = try_into_key_value( item ).or_err( || format!( "\"{}.{}.{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, nth_key, subproperty_name, item ) )?;
+                                                            to.push( item );
+                                                        }
+                                                    } else {
+                                                        let item = try_into_key_value( item ).or_err( || format!( "\"{}.{}.{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, nth_key, subproperty_name, item ) )?;
+                                                        to.push( item );
+                                                    }
+                                                },
+                                                subproperty_name => {
+                                                    return err( format!( "unrecognized key: \"{}.{}.{}.{}.{}\"", toplevel_key, nth, property_name, nth_key, subproperty_name ) )
+                                                }
+                                            }
+                                        }
+
+                                        let from = from.or_err( || format!( "missing \"{}.{}.{}.{}.from\"", toplevel_key, nth, property_name, nth_key ) )?;
+                                        if to.is_empty() {
+                                            return err( format!( "missing \"{}.{}.{}.{}.to\"", toplevel_key, nth, property_name, nth_key ) );
+                                        }
+
+                                        keys.push( (from, to) );
+                                    }
+                                },
+                                "generate-repeat" => {
+                                    let item = item.as_bool().or_err( || format!( "\"{}.{}.{}\" is not a boolean", toplevel_key, nth, property_name ) )?;
+                                    generate_repeat = Some( item );
+                                },
+                                "repeat-delay" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = humantime::parse_duration( item ).ok().or_err( || format!( "\"{}.{}.{}\" is not a valid duration", toplevel_key, nth, property_name ) )?;
+                                    repeat_delay = Some( item );
+                                },
+                                "repeat-period" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = humantime::parse_duration( item ).ok().or_err( || format!( "\"{}.{}.{}\" is not a valid duration", toplevel_key, nth, property_name ) )?;
+                                    repeat_period = Some( item );
+                                },
+                                property_name => {
+                                    return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
+                                }
+                            }
+                        }
+
+                        let device = device.or_err( || format!( "missing \"{}.{}.device\"", toplevel_key, nth ) )?;
+                        let target = target.or_err( || format!( "missing \"{}.{}.target\"", toplevel_key, nth ) )?;
+                        remaps.push( Remap {
+                            device,
+                            target,
+                            keys,
+                            generate_repeat: build_repeat( generate_repeat, repeat_delay, repeat_period )
+                        })
+                    }
+                },
+                "merge" => {
+                    let item = item.as_array().or_err( || format!( "\"{}\" is not an array", toplevel_key ) )?;
+                    for (nth, item) in item.iter().enumerate() {
+                        let item = item.as_table().or_err( || format!( "\"{}.{}\" is not a table", toplevel_key, nth ) )?;
+
+                        let mut target = None;
+                        let mut keys = Vec::new();
+                        let mut axes = Vec::new();
+                        for (property_name, item) in item.iter() {
+                            match property_name.as_str() {
+                                "target" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    target = Some( item );
+                                },
+                                "keys" => {
+                                    let item = item.as_array().or_err( || format!( "\"{}.{}.{}\" is not an array", toplevel_key, nth, property_name ) )?;
+                                    for (nth_key, item) in item.iter().enumerate() {
+                                        let item = item.as_table().or_err( || format!( "\"{}.{}.{}.{}\" is not a table", toplevel_key, nth, property_name, nth_key ) )?;
+                                        let mut device = None;
+                                        let mut from = None;
+                                        let mut to = None;
+                                        for (subproperty_name, item) in item.iter() {
+                                            match subproperty_name.as_str() {
+                                                "device" => {
+                                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}.{}.{}\" is not a string", toplevel_key, nth, property_name, nth_key, subproperty_name ) )?.to_owned();
+                                                    device = Some( item );
+                                                },
+                                                "from" => {
+                                                    let item = try_into_key_value( item ).or_err( || format!( "\"{}.{}.{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, nth_key, subproperty_name, item ) )?;
+                                                    from = Some( item );
+                                                },
+                                                "to" => {
+                                                    let item = try_into_key_value( item ).or_err( || format!( "\"{}.{}.{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, nth_key, subproperty_name, item ) )?;
+                                                    to = Some( item );
+                                                },
+                                                subproperty_name => {
+                                                    return err( format!( "unrecognized key: \"{}.{}.{}.{}.{}\"", toplevel_key, nth, property_name, nth_key, subproperty_name ) )
+                                                }
+                                            }
+                                        }
+
+                                        let device = device.or_err( || format!( "missing \"{}.{}.{}.{}.device\"", toplevel_key, nth, property_name, nth_key ) )?;
+                                        let from = from.or_err( || format!( "missing \"{}.{}.{}.{}.from\"", toplevel_key, nth, property_name, nth_key ) )?;
+                                        keys.push( MergeKey {
+                                            device,
+                                            from,
+                                            to: to.unwrap_or( from )
+                                        });
+                                    }
+                                },
+                                "axes" => {
+                                    let item = item.as_array().or_err( || format!( "\"{}.{}.{}\" is not an array", toplevel_key, nth, property_name ) )?;
+                                    for (nth_axis, item) in item.iter().enumerate() {
+                                        let item = item.as_table().or_err( || format!( "\"{}.{}.{}.{}\" is not a table", toplevel_key, nth, property_name, nth_axis ) )?;
+                                        let mut device = None;
+                                        let mut from = None;
+                                        let mut to = None;
+                                        let mut range = None;
+                                        let mut transform = AxisTransform::default();
+                                        for (subproperty_name, item) in item.iter() {
+                                            match subproperty_name.as_str() {
+                                                "device" => {
+                                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}.{}.{}\" is not a string", toplevel_key, nth, property_name, nth_axis, subproperty_name ) )?.to_owned();
+                                                    device = Some( item );
+                                                },
+                                                "from" => {
+                                                    let item = try_into_abs_value( item ).or_err( || format!( "\"{}.{}.{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, nth_axis, subproperty_name, item ) )?;
+                                                    from = Some( item );
+                                                },
+                                                "to" => {
+                                                    let item = try_into_abs_value( item ).or_err( || format!( "\"{}.{}.{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, nth_axis, subproperty_name, item ) )?;
+                                                    to = Some( item );
+                                                },
+                                                "range" => {
+                                                    let item = item.as_array()
+                                                        .filter( |item| item.len() == 2 )
+                                                        .and_then( |item| Some( (item[ 0 ].as_integer()?.try_into().ok()?, item[ 1 ].as_integer()?.try_into().ok()?) ) )
+                                                        .or_err( || format!( "\"{}.{}.{}.{}.{}\" is not a [minimum, maximum] pair", toplevel_key, nth, property_name, nth_axis, subproperty_name ) )?;
+                                                    range = Some( item );
+                                                },
+                                                "invert" => {
+                                                    let item = item.as_bool().or_err( || format!( "\"{}.{}.{}.{}.{}\" is not a boolean", toplevel_key, nth, property_name, nth_axis, subproperty_name ) )?;
+                                                    transform.invert = item;
+                                                },
+                                                "deadzone" | "outer-deadzone" => {
+                                                    let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}.{}.{}\" is not a number", toplevel_key, nth, property_name, nth_axis, subproperty_name ) )?;
+                                                    if !(0.0..1.0).contains( &item ) {
+                                                        return err( format!( "\"{}.{}.{}.{}.{}\" is out of range; it should be between 0.0 and 1.0", toplevel_key, nth, property_name, nth_axis, subproperty_name ) );
+                                                    }
+
+                                                    if subproperty_name == "deadzone" {
+                                                        transform.deadzone = item;
+                                                    } else {
+                                                        transform.outer_deadzone = item;
+                                                    }
+                                                },
+                                                "radial-with" => {
+                                                    let item = try_into_abs_value( item ).or_err( || format!( "\"{}.{}.{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, nth_axis, subproperty_name, item ) )?;
+                                                    transform.radial_with = Some( item );
+                                                },
+                                                "curve" => {
+                                                    if let Some( points ) = item.as_array() {
+                                                        let mut table = Vec::new();
+                                                        for point in points.iter() {
+                                                            let point = point.as_array()
+                                                                .filter( |point| point.len() == 2 )
+                                                                .and_then( |point| {
+                                                                    let x = point[ 0 ].as_float().or_else( || point[ 0 ].as_integer().map( |value| value as f64 ) )?;
+                                                                    let y = point[ 1 ].as_float().or_else( || point[ 1 ].as_integer().map( |value| value as f64 ) )?;
+                                                                    Some( (x, y) )
+                                                                })
+                                                                .filter( |&(x, y)| (0.0..=1.0).contains( &x ) && (0.0..=1.0).contains( &y ) )
+                                                                .or_err( || format!( "\"{}.{}.{}.{}.{}\" has an invalid point: '{}'; it should be an [x, y] pair between 0.0 and 1.0", toplevel_key, nth, property_name, nth_axis, subproperty_name, point ) )?;
+                                                            table.push( point );
+                                                        }
+
+                                                        if table.is_empty() {
+                                                            return err( format!( "\"{}.{}.{}.{}.{}\" is empty", toplevel_key, nth, property_name, nth_axis, subproperty_name ) );
+                                                        }
+
+                                                        table.sort_by( |a, b| a.0.partial_cmp( &b.0 ).unwrap() );
+                                                        transform.curve = Curve::Points( table );
+                                                    } else {
+                                                        let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}.{}.{}\" is neither an exponent nor an array of points", toplevel_key, nth, property_name, nth_axis, subproperty_name ) )?;
+                                                        if item <= 0.0 {
+                                                            return err( format!( "\"{}.{}.{}.{}.{}\" has to be positive", toplevel_key, nth, property_name, nth_axis, subproperty_name ) );
+                                                        }
+                                                        transform.curve = Curve::Exponent( item );
+                                                    }
+                                                },
+                                                subproperty_name => {
+                                                    return err( format!( "unrecognized key: \"{}.{}.{}.{}.{}\"", toplevel_key, nth, property_name, nth_axis, subproperty_name ) )
+                                                }
+                                            }
+                                        }
+
+                                        let device = device.or_err( || format!( "missing \"{}.{}.{}.{}.device\"", toplevel_key, nth, property_name, nth_axis ) )?;
+                                        let from = from.or_err( || format!( "missing \"{}.{}.{}.{}.from\"", toplevel_key, nth, property_name, nth_axis ) )?;
+                                        if transform.deadzone + transform.outer_deadzone >= 1.0 {
+                                            return err( format!( "\"{}.{}.{}.{}\" has overlapping deadzones", toplevel_key, nth, property_name, nth_axis ) );
+                                        }
+
+                                        axes.push( MergeAxis {
+                                            device,
+                                            from,
+                                            to: to.unwrap_or( from ),
+                                            range,
+                                            transform
+                                        });
+                                    }
+                                },
+                                property_name => {
+                                    return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
+                                }
+                            }
+                        }
+
+                        let target = target.or_err( || format!( "missing \"{}.{}.target\"", toplevel_key, nth ) )?;
+                        merges.push( Merge {
+                            target,
+                            keys,
+                            axes
+                        })
+                    }
+                },
+                "stick-to-mouse" | "mouse-to-stick" => {
+                    let is_stick_to_mouse = toplevel_key == "stick-to-mouse";
+                    let item = item.as_array().or_err( || format!( "\"{}\" is not an array", toplevel_key ) )?;
+                    for (nth, item) in item.iter().enumerate() {
+                        let item = item.as_table().or_err( || format!( "\"{}.{}\" is not a table", toplevel_key, nth ) )?;
+
+                        let mut device = None;
+                        let mut target = None;
+                        let mut x_axis = None;
+                        let mut y_axis = None;
+                        let mut tick = None;
+                        let mut deadzone = None;
+                        let mut speed = None;
+                        let mut acceleration = None;
+                        let mut sensitivity = None;
+                        let mut recenter = None;
+                        for (property_name, item) in item.iter() {
+                            match property_name.as_str() {
+                                "device" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    device = Some( item );
+                                },
+                                "target" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    target = Some( item );
+                                },
+                                "x-axis" | "y-axis" => {
+                                    let item = try_into_abs_value( item ).or_err( || format!( "\"{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                    if property_name == "x-axis" {
+                                        x_axis = Some( item );
+                                    } else {
+                                        y_axis = Some( item );
+                                    }
+                                },
+                                "tick" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = humantime::parse_duration( item ).ok().or_err( || format!( "\"{}.{}.{}\" is not a valid duration", toplevel_key, nth, property_name ) )?;
+                                    if item < Duration::from_millis( 1 ) {
+                                        return err( format!( "\"{}.{}.{}\" cannot be shorter than 1ms", toplevel_key, nth, property_name ) );
+                                    }
+                                    tick = Some( item );
+                                },
+                                "deadzone" if is_stick_to_mouse => {
+                                    let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}\" is not a number", toplevel_key, nth, property_name ) )?;
+                                    if !(0.0..1.0).contains( &item ) {
+                                        return err( format!( "\"{}.{}.{}\" is out of range; it should be between 0.0 and 1.0", toplevel_key, nth, property_name ) );
+                                    }
+                                    deadzone = Some( item );
+                                },
+                                "speed" | "acceleration" if is_stick_to_mouse => {
+                                    let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}\" is not a number", toplevel_key, nth, property_name ) )?;
+                                    if item <= 0.0 {
+                                        return err( format!( "\"{}.{}.{}\" has to be positive", toplevel_key, nth, property_name ) );
+                                    }
+                                    if property_name == "speed" {
+                                        speed = Some( item );
+                                    } else {
+                                        acceleration = Some( item );
+                                    }
+                                },
+                                "sensitivity" if !is_stick_to_mouse => {
+                                    let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}\" is not a number", toplevel_key, nth, property_name ) )?;
+                                    if item <= 0.0 {
+                                        return err( format!( "\"{}.{}.{}\" has to be positive", toplevel_key, nth, property_name ) );
+                                    }
+                                    sensitivity = Some( item );
+                                },
+                                "recenter" if !is_stick_to_mouse => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = humantime::parse_duration( item ).ok().or_err( || format!( "\"{}.{}.{}\" is not a valid duration", toplevel_key, nth, property_name ) )?;
+                                    recenter = Some( item );
+                                },
+                                property_name => {
+                                    return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
+                                }
+                            }
+                        }
+
+                        let device = device.or_err( || format!( "missing \"{}.{}.device\"", toplevel_key, nth ) )?;
+                        let target = target.or_err( || format!( "missing \"{}.{}.target\"", toplevel_key, nth ) )?;
+                        let mode = if is_stick_to_mouse {
+                            EmulationMode::StickToMouse {
+                                deadzone: deadzone.unwrap_or( 0.1 ),
+                                speed: speed.unwrap_or( 1000.0 ),
+                                acceleration: acceleration.unwrap_or( 2.0 )
+                            }
+                        } else {
+                            EmulationMode::MouseToStick {
+                                sensitivity: sensitivity.unwrap_or( 0.01 ),
+                                recenter
+                            }
+                        };
+
+                        emulations.push( Emulation {
+                            device,
+                            target,
+                            x_axis: x_axis.unwrap_or( AbsoluteAxis::X ),
+                            y_axis: y_axis.unwrap_or( AbsoluteAxis::Y ),
+                            tick: tick.unwrap_or( Duration::from_millis( 8 ) ),
+                            mode
+                        });
+                    }
+                },
+                "pointer-profile" => {
+                    let item = item.as_array().or_err( || format!( "\"{}\" is not an array", toplevel_key ) )?;
+                    for (nth, item) in item.iter().enumerate() {
+                        let item = item.as_table().or_err( || format!( "\"{}.{}\" is not a table", toplevel_key, nth ) )?;
+
+                        let mut name = None;
+                        let mut sensitivity = None;
+                        let mut acceleration = None;
+                        let mut threshold = None;
+                        let mut slope = None;
+                        let mut max_factor = None;
+                        for (property_name, item) in item.iter() {
+                            match property_name.as_str() {
+                                "name" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    name = Some( item );
+                                },
+                                "sensitivity" | "threshold" | "slope" | "max-factor" => {
+                                    let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}\" is not a number", toplevel_key, nth, property_name ) )?;
+                                    if item < 0.0 || (item == 0.0 && property_name != "threshold") {
+                                        return err( format!( "\"{}.{}.{}\" has to be positive", toplevel_key, nth, property_name ) );
+                                    }
+                                    match property_name.as_str() {
+                                        "sensitivity" => sensitivity = Some( item ),
+                                        "threshold" => threshold = Some( item ),
+                                        "slope" => slope = Some( item ),
+                                        _ => max_factor = Some( item )
+                                    }
+                                },
+                                "acceleration" => {
+                                    if let Some( points ) = item.as_array() {
+                                        let mut table = Vec::new();
+                                        for point in points.iter() {
+                                            let point = point.as_array()
+                                                .filter( |point| point.len() == 2 )
+                                                .and_then( |point| {
+                                                    let speed = point[ 0 ].as_float().or_else( || point[ 0 ].as_integer().map( |value| value as f64 ) )?;
+                                                    let factor = point[ 1 ].as_float().or_else( || point[ 1 ].as_integer().map( |value| value as f64 ) )?;
+                                                    Some( (speed, factor) )
+                                                })
+                                                .filter( |&(speed, factor)| speed >= 0.0 && factor >= 0.0 )
+                                                .or_err( || format!( "\"{}.{}.{}\" has an invalid point: '{}'; it should be a non-negative [speed, factor] pair", toplevel_key, nth, property_name, point ) )?;
+                                            table.push( point );
+                                        }
+
+                                        if table.is_empty() {
+                                            return err( format!( "\"{}.{}.{}\" is empty", toplevel_key, nth, property_name ) );
+                                        }
+
+                                        table.sort_by( |a, b| a.0.partial_cmp( &b.0 ).unwrap() );
+                                        acceleration = Some( Acceleration::Custom( table ) );
+                                    } else {
+                                        let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is neither a string nor an array of points", toplevel_key, nth, property_name ) )?;
+                                        acceleration = match item {
+                                            "flat" => Some( Acceleration::Flat ),
+                                            "adaptive" => None,
+                                            _ => return err( format!( "\"{}.{}.{}\" has an invalid value: '{}'; expected \"flat\" or \"adaptive\"", toplevel_key, nth, property_name, item ) )
+                                        };
+                                    }
+                                },
+                                property_name => {
+                                    return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
+                                }
+                            }
+                        }
+
+                        let name = name.or_err( || format!( "missing \"{}.{}.name\"", toplevel_key, nth ) )?;
+                        let acceleration = acceleration.unwrap_or_else( || Acceleration::Adaptive {
+                            threshold: threshold.unwrap_or( 0.4 ),
+                            slope: slope.unwrap_or( 1.0 ),
+                            max_factor: max_factor.unwrap_or( 3.0 )
+                        });
+
+                        if !matches!( acceleration, Acceleration::Adaptive { .. } ) && (threshold.is_some() || slope.is_some() || max_factor.is_some()) {
+                            return err( format!( "\"{}.{}\" sets the parameters of the adaptive acceleration, but uses a different one", toplevel_key, nth ) );
+                        }
+
+                        if pointer_profiles.contains_key( &name ) {
+                            return err( format!( "duplicate pointer profile: \"{}\"", name ) );
+                        }
+
+                        pointer_profiles.insert( name, PointerProfile {
+                            sensitivity: sensitivity.unwrap_or( 1.0 ),
+                            acceleration
+                        });
+                    }
+                },
+                "pointer" => {
+                    let item = item.as_array().or_err( || format!( "\"{}\" is not an array", toplevel_key ) )?;
+                    for (nth, item) in item.iter().enumerate() {
+                        let item = item.as_table().or_err( || format!( "\"{}.{}\" is not a table", toplevel_key, nth ) )?;
+
+                        let mut device = None;
+                        let mut dpi = None;
+                        let mut profile = None;
+                        for (property_name, item) in item.iter() {
+                            match property_name.as_str() {
+                                "device" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    device = Some( item );
+                                },
+                                "dpi" => {
+                                    let item = item.as_integer().or_err( || format!( "\"{}.{}.{}\" is not an integer", toplevel_key, nth, property_name ) )?;
+                                    let item = item.try_into().ok().filter( |&dpi: &u32| dpi > 0 ).or_err( || format!( "\"{}.{}.{}\" is out of range", toplevel_key, nth, property_name ) )?;
+                                    dpi = Some( item );
+                                },
+                                "profile" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    profile = Some( item );
+                                },
+                                property_name => {
+                                    return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
+                                }
+                            }
+                        }
+
+                        let device = device.or_err( || format!( "missing \"{}.{}.device\"", toplevel_key, nth ) )?;
+                        let profile = profile.or_err( || format!( "missing \"{}.{}.profile\"", toplevel_key, nth ) )?;
+                        pointers.push( Pointer {
+                            device,
+                            dpi: dpi.unwrap_or( REFERENCE_DPI ),
+                            profile
+                        });
+                    }
+                },
+                "button-scroll" => {
+                    let item = item.as_array().or_err( || format!( "\"{}\" is not an array", toplevel_key ) )?;
+                    for (nth, item) in item.iter().enumerate() {
+                        let item = item.as_table().or_err( || format!( "\"{}.{}\" is not a table", toplevel_key, nth ) )?;
+
+                        let mut device = None;
+                        let mut target = None;
+                        let mut button = None;
+                        let mut threshold = None;
+                        let mut distance = None;
+                        let mut horizontal = None;
+                        let mut natural = None;
+                        for (property_name, item) in item.iter() {
+                            match property_name.as_str() {
+                                "device" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    device = Some( item );
+                                },
+                                "target" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    target = Some( item );
+                                },
+                                "button" => {
+                                    let item = try_into_key_value( item ).or_err( || format!( "\"{}.{}.{}\" has an invalid value: '{}'", toplevel_key, nth, property_name, item ) )?;
+                                    button = Some( item );
+                                },
+                                "threshold" => {
+                                    let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}\" is not a number", toplevel_key, nth, property_name ) )?;
+                                    if item < 0.0 {
+                                        return err( format!( "\"{}.{}.{}\" cannot be negative", toplevel_key, nth, property_name ) );
+                                    }
+                                    threshold = Some( item );
+                                },
+                                "distance" => {
+                                    let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}\" is not a number", toplevel_key, nth, property_name ) )?;
+                                    if item <= 0.0 {
+                                        return err( format!( "\"{}.{}.{}\" has to be positive", toplevel_key, nth, property_name ) );
+                                    }
+                                    distance = Some( item );
+                                },
+                                "horizontal" | "natural" => {
+                                    let item = item.as_bool().or_err( || format!( "\"{}.{}.{}\" is not a boolean", toplevel_key, nth, property_name ) )?;
+                                    if property_name == "horizontal" {
+                                        horizontal = Some( item );
+                                    } else {
+                                        natural = Some( item );
+                                    }
+                                },
+                                property_name => {
+                                    return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
+                                }
+                            }
+                        }
+
+                        let device = device.or_err( || format!( "missing \"{}.{}.device\"", toplevel_key, nth ) )?;
+                        let target = target.or_err( || format!( "missing \"{}.{}.target\"", toplevel_key, nth ) )?;
+                        button_scrolls.push( ButtonScroll {
+                            device,
+                            target,
+                            button: button.unwrap_or( Key::MouseMiddle ),
+                            threshold: threshold.unwrap_or( 8.0 ),
+                            distance: distance.unwrap_or( 20.0 ),
+                            horizontal: horizontal.unwrap_or( true ),
+                            natural: natural.unwrap_or( false )
+                        });
+                    }
+                },
+                "gestures" => {
+                    let item = item.as_array().or_err( || format!( "\"{}\" is not an array", toplevel_key ) )?;
+                    for (nth, item) in item.iter().enumerate() {
+                        let item = item.as_table().or_err( || format!( "\"{}.{}\" is not a table", toplevel_key, nth ) )?;
+
+                        let mut device = None;
+                        let mut swipe_distance = None;
+                        let mut pinch_scale = None;
+                        let mut tap_time = None;
+                        let mut tap_distance = None;
+                        let mut edge_margin = None;
+                        for (property_name, item) in item.iter() {
+                            match property_name.as_str() {
+                                "device" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    device = Some( item );
+                                },
+                                "swipe-distance" | "tap-distance" | "edge-margin" => {
+                                    let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}\" is not a number", toplevel_key, nth, property_name ) )?;
+                                    if !(0.0..=1.0).contains( &item ) {
+                                        return err( format!( "\"{}.{}.{}\" is out of range; it should be between 0.0 and 1.0", toplevel_key, nth, property_name ) );
+                                    }
+                                    match property_name.as_str() {
+                                        "swipe-distance" => swipe_distance = Some( item ),
+                                        "tap-distance" => tap_distance = Some( item ),
+                                        _ => edge_margin = Some( item )
+                                    }
+                                },
+                                "pinch-scale" => {
+                                    let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}\" is not a number", toplevel_key, nth, property_name ) )?;
+                                    if item <= 1.0 {
+                                        return err( format!( "\"{}.{}.{}\" has to be greater than 1.0", toplevel_key, nth, property_name ) );
+                                    }
+                                    pinch_scale = Some( item );
+                                },
+                                "tap-time" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?;
+                                    let item = humantime::parse_duration( item ).ok().or_err( || format!( "\"{}.{}.{}\" is not a valid duration", toplevel_key, nth, property_name ) )?;
+                                    tap_time = Some( item );
+                                },
+                                property_name => {
+                                    return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
+                                }
+                            }
+                        }
+
+                        let device = device.or_err( || format!( "missing \"{}.{}.device\"", toplevel_key, nth ) )?;
+                        gestures.push( Gestures {
+                            device,
+                            swipe_distance: swipe_distance.unwrap_or( 0.15 ),
+                            pinch_scale: pinch_scale.unwrap_or( 1.3 ),
+                            tap_time: tap_time.unwrap_or( Duration::from_millis( 200 ) ),
+                            tap_distance: tap_distance.unwrap_or( 0.03 ),
+                            edge_margin: edge_margin.unwrap_or( 0.05 )
+                        });
+                    }
+                },
+                "absolute-mapping" => {
+                    let item = item.as_array().or_err( || format!( "\"{}\" is not an array", toplevel_key ) )?;
+                    for (nth, item) in item.iter().enumerate() {
+                        let item = item.as_table().or_err( || format!( "\"{}.{}\" is not a table", toplevel_key, nth ) )?;
+
+                        let mut device = None;
+                        let mut rotation = None;
+                        let mut left_handed = None;
+                        let mut area = None;
+                        let mut region = None;
+                        let mut aspect_ratio = None;
+                        for (property_name, item) in item.iter() {
+                            match property_name.as_str() {
+                                "device" => {
+                                    let item = item.as_str().or_err( || format!( "\"{}.{}.{}\" is not a string", toplevel_key, nth, property_name ) )?.to_owned();
+                                    device = Some( item );
+                                },
+                                "rotation" => {
+                                    let item = item.as_integer().or_err( || format!( "\"{}.{}.{}\" is not an integer", toplevel_key, nth, property_name ) )?;
+                                    if ![0, 90, 180, 270].contains( &item ) {
+                                        return err( format!( "\"{}.{}.{}\" has an invalid value: '{}'; expected 0, 90, 180 or 270", toplevel_key, nth, property_name, item ) );
+                                    }
+                                    rotation = Some( item as u16 );
+                                },
+                                "left-handed" => {
+                                    let item = item.as_bool().or_err( || format!( "\"{}.{}.{}\" is not a boolean", toplevel_key, nth, property_name ) )?;
+                                    left_handed = Some( item );
+                                },
+                                "area" | "region" => {
+                                    let item = item.as_array()
+                                        .filter( |item| item.len() == 4 )
+                                        .and_then( |item| {
+                                            let mut values = [0.0; 4];
+                                            for (value, item) in values.iter_mut().zip( item.iter() ) {
+                                                *value = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) )?;
+                                            }
+                                            Some( (values[ 0 ], values[ 1 ], values[ 2 ], values[ 3 ]) )
+                                        })
+                                        .filter( |&(left, top, right, bottom)| {
+                                            (0.0..=1.0).contains( &left ) && (0.0..=1.0).contains( &top ) &&
+                                            (0.0..=1.0).contains( &right ) && (0.0..=1.0).contains( &bottom ) &&
+                                            left < right && top < bottom
+                                        })
+                                        .or_err( || format!( "\"{}.{}.{}\" is not a valid [left, top, right, bottom] rectangle between 0.0 and 1.0", toplevel_key, nth, property_name ) )?;
+
+                                    if property_name == "area" {
+                                        area = Some( item );
+                                    } else {
+                                        region = Some( item );
+                                    }
+                                },
+                                "aspect-ratio" => {
+                                    let item = item.as_float().or_else( || item.as_integer().map( |value| value as f64 ) ).or_err( || format!( "\"{}.{}.{}\" is not a number", toplevel_key, nth, property_name ) )?;
+                                    if item <= 0.0 {
+                                        return err( format!( "\"{}.{}.{}\" has to be positive", toplevel_key, nth, property_name ) );
+                                    }
+                                    aspect_ratio = Some( item );
+                                },
+                                property_name => {
+                                    return err( format!( "unrecognized key: \"{}.{}.{}\"", toplevel_key, nth, property_name ) )
+                                }
+                            }
+                        }
+
+                        let device = device.or_err( || format!( "missing \"{}.{}.device\"", toplevel_key, nth ) )?;
+                        absolute_mappings.push( AbsoluteMapping {
+                            device,
+                            rotation: rotation.unwrap_or( 0 ),
+                            left_handed: left_handed.unwrap_or( false ),
+                            area: area.unwrap_or( (0.0, 0.0, 1.0, 1.0) ),
+                            region: region.unwrap_or( (0.0, 0.0, 1.0, 1.0) ),
+                            aspect_ratio
+                        });
+                    }
+                },
+                toplevel_key => return err( format!( "unrecognized key: \"{}\"", toplevel_key ) )
+            }
+        }
+
+        for script in &scripts {
+            if !device_filters.contains_key( &script.device ) {
+                return err( format!( "[[script]] refers to a non-existing device filter: \"{}\"", script.device ) );
+            }
+
+            if let Some( ref target ) = script.default_target {
+                if !virtual_devices.contains_key( target ) {
+                    return err( format!( "[[script]]'s 'default-target' refers to a non-existing virtual device: \"{}\"", target ) );
+                }
+            }
+
+            if let Some( ref target ) = script.passthrough_to {
+                if !virtual_devices.contains_key( target ) {
+                    return err( format!( "[[script]]'s 'passthrough-to' refers to a non-existing virtual device: \"{}\"", target ) );
+                }
+            }
+        }
+
+        for remap in &remaps {
+            if !device_filters.contains_key( &remap.device ) {
+                return err( format!( "[[remap]] refers to a non-existing device filter: \"{}\"", remap.device ) );
+            }
+
+            let virtual_device = virtual_devices.get( &remap.target ).or_err( || format!( "[[remap]] refers to a non-existing virtual device: \"{}\"", remap.target ) )?;
+            if remap.generate_repeat.is_some() && virtual_device.repeat.is_some() {
+                return err( format!( "[[remap]] has 'generate-repeat' while its target \"{}\" already has the kernel's autorepeat enabled", remap.target ) );
+            }
+        }
+
+        for merge in &merges {
+            let virtual_device = virtual_devices.get( &merge.target ).or_err( || format!( "[[merge]] refers to a non-existing virtual device: \"{}\"", merge.target ) )?;
+            for device in merge.keys.iter().map( |key| &key.device ).chain( merge.axes.iter().map( |axis| &axis.device ) ) {
+                if !device_filters.contains_key( device ) {
+                    return err( format!( "[[merge]] refers to a non-existing device filter: \"{}\"", device ) );
+                }
+            }
+
+            for key in &merge.keys {
+                if !virtual_device.has_key( key.to ) {
+                    return err( format!( "[[merge]] maps to a key which wasn't declared in \"{}\": {}", merge.target, key.to ) );
+                }
+            }
+
+            for axis in &merge.axes {
+                if !virtual_device.abs_bits.iter().any( |bit| bit.axis == axis.to ) {
+                    return err( format!( "[[merge]] maps to an axis which wasn't declared in \"{}\": {}", merge.target, axis.to ) );
+                }
+
+                if let Some( other_axis ) = axis.transform.radial_with {
+                    if !merge.axes.iter().any( |other| other.device == axis.device && other.from == other_axis ) {
+                        return err( format!( "[[merge]]'s 'radial-with' refers to an axis which isn't merged from \"{}\": {}", axis.device, other_axis ) );
+                    }
+                }
+            }
+        }
+
+        for emulation in &emulations {
+            if !device_filters.contains_key( &emulation.device ) {
+                return err( format!( "[[{}]] refers to a non-existing device filter: \"{}\"", emulation.mode.name(), emulation.device ) );
+            }
+
+            let virtual_device = virtual_devices.get( &emulation.target ).or_err( || format!( "[[{}]] refers to a non-existing virtual device: \"{}\"", emulation.mode.name(), emulation.target ) )?;
+            match emulation.mode {
+                EmulationMode::StickToMouse { .. } => {
+                    let has_pointer = virtual_device.preset == Some( DevicePreset::Mouse ) || (
+                        virtual_device.rel_bits.contains( &RelativeAxis::X ) && virtual_device.rel_bits.contains( &RelativeAxis::Y )
+                    );
+
+                    if !has_pointer {
+                        return err( format!( "[[{}]]'s target has no relative X and Y axes: \"{}\"", emulation.mode.name(), emulation.target ) );
+                    }
+                },
+                EmulationMode::MouseToStick { .. } => {
+                    for &axis in &[emulation.x_axis, emulation.y_axis] {
+                        if !virtual_device.abs_bits.iter().any( |bit| bit.axis == axis ) {
+                            return err( format!( "[[{}]] maps to an axis which wasn't declared in \"{}\": {}", emulation.mode.name(), emulation.target, axis ) );
+                        }
+                    }
+                }
+            }
+        }
+
+        for pointer in &pointers {
+            if !device_filters.contains_key( &pointer.device ) {
+                return err( format!( "[[pointer]] refers to a non-existing device filter: \"{}\"", pointer.device ) );
+            }
+
+            if !pointer_profiles.contains_key( &pointer.profile ) {
+                return err( format!( "[[pointer]] refers to a non-existing pointer profile: \"{}\"", pointer.profile ) );
+            }
+        }
+
+        for button_scroll in &button_scrolls {
+            if !device_filters.contains_key( &button_scroll.device ) {
+                return err( format!( "[[button-scroll]] refers to a non-existing device filter: \"{}\"", button_scroll.device ) );
+            }
+
+            if !virtual_devices.contains_key( &button_scroll.target ) {
+                return err( format!( "[[button-scroll]] refers to a non-existing virtual device: \"{}\"", button_scroll.target ) );
+            }
+        }
+
+        for gestures in &gestures {
+            if !device_filters.contains_key( &gestures.device ) {
+                return err( format!( "[[gestures]] refers to a non-existing device filter: \"{}\"", gestures.device ) );
+            }
+        }
+
+        for absolute_mapping in &absolute_mappings {
+            if !device_filters.contains_key( &absolute_mapping.device ) {
+                return err( format!( "[[absolute-mapping]] refers to a non-existing device filter: \"{}\"", absolute_mapping.device ) );
+            }
+        }
+
+        for (virtual_device_name, virtual_device) in &virtual_devices {
+            if device_filters.contains_key( virtual_device_name ) {
+                return err( format!( "same name used as a device filter and a virtual device: \"{}\"", virtual_device_name ) );
+            }
+
+            for target in &virtual_device.redirect_force_feedback_to {
+                if !device_filters.contains_key( &target.device ) {
+                    return err( format!( "[[virtual-device]]'s 'redirect-force-feedback-to' refers to a non-existing device filter: \"{}\"", target.device ) );
+                }
+            }
+
+            for target in &virtual_device.mirror_leds_to {
+                if !device_filters.contains_key( target ) {
+                    return err( format!( "[[virtual-device]]'s 'mirror-leds-to' refers to a non-existing device filter: \"{}\"", target ) );
+                }
+            }
+
+            if virtual_device.on_force_feedback.is_some() && virtual_device.ff_bits.is_empty() {
+                return err( format!( "[[virtual-device]] \"{}\" has an 'on-force-feedback' script but no 'force-feedback' bits", virtual_device_name ) );
             }
         }
 
         Ok( Config {
+            path: path.to_owned(),
             device_filters,
             virtual_devices,
             scripts,
+            remaps,
+            merges,
+            emulations,
+            pointer_profiles,
+            pointers,
+            button_scrolls,
+            gestures,
+            absolute_mappings
         })
     }
 }
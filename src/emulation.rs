@@ -0,0 +1,310 @@
+use {
+    std::{
+        collections::{
+            HashMap
+        },
+        time::{
+            Duration,
+            Instant
+        }
+    },
+    linux_input::{
+        AbsoluteAxis,
+        AbsoluteAxisBit,
+        InputEventBody,
+        RelativeAxis
+    },
+    parking_lot::{
+        Condvar,
+        Mutex
+    },
+    crate::{
+        config::{
+            Curve,
+            EmulationMode
+        }
+    }
+};
+
+/// The input from a single source device.
+#[derive(Default)]
+struct SourceState {
+    x: f64,
+    y: f64,
+    /// The fractional part of the pointer motion which wasn't emitted yet.
+    remainder: (f64, f64)
+}
+
+#[derive(Default)]
+struct State {
+    /// Keyed by the source device, so that unplugging one doesn't affect the others.
+    sources: HashMap< usize, SourceState >,
+    last_emitted: Option< (i32, i32) >,
+    /// Cleared once ticking won't generate anything until new input arrives.
+    is_active: bool
+}
+
+/// A built-in mode which continuously generates events on a fixed tick.
+pub struct Emulation {
+    pub device: String,
+    pub target: String,
+    pub tick: Duration,
+    x_axis: AbsoluteAxis,
+    y_axis: AbsoluteAxis,
+    mode: EmulationMode,
+    /// The ranges of the target's axes in the mouse-to-stick mode.
+    target_ranges: ((i32, i32), (i32, i32)),
+    state: Mutex< State >,
+    condvar: Condvar
+}
+
+const RECENTER_EPSILON: f64 = 0.0001;
+
+fn find_range( axes: &[AbsoluteAxisBit], axis: AbsoluteAxis ) -> Option< (i32, i32) > {
+    axes.iter().find( |bit| bit.axis == axis ).map( |bit| (bit.minimum, bit.maximum) )
+}
+
+impl Emulation {
+    pub fn new( emulation: &crate::config::Emulation, target_axes: &[AbsoluteAxisBit] ) -> Self {
+        let target_ranges = (
+            find_range( target_axes, emulation.x_axis ).unwrap_or( (-1, 1) ),
+            find_range( target_axes, emulation.y_axis ).unwrap_or( (-1, 1) )
+        );
+
+        Emulation {
+            device: emulation.device.clone(),
+            target: emulation.target.clone(),
+            tick: emulation.tick,
+            x_axis: emulation.x_axis,
+            y_axis: emulation.y_axis,
+            mode: emulation.mode.clone(),
+            target_ranges,
+            state: Mutex::new( State::default() ),
+            condvar: Condvar::new()
+        }
+    }
+
+    /// The position of the emulated stick, combined from every source.
+    fn stick_position( &self, state: &State ) -> (i32, i32) {
+        let x = state.sources.values().map( |source| source.x ).sum::< f64 >().clamp( -1.0, 1.0 );
+        let y = state.sources.values().map( |source| source.y ).sum::< f64 >().clamp( -1.0, 1.0 );
+        let ((x_min, x_max), (y_min, y_max)) = self.target_ranges;
+        (
+            crate::axis::denormalize( x, x_min, x_max ),
+            crate::axis::denormalize( y, y_min, y_max )
+        )
+    }
+
+    fn wake_up( &self, state: &mut State ) {
+        state.is_active = true;
+        self.condvar.notify_all();
+    }
+
+    pub fn is_active( &self ) -> bool {
+        self.state.lock().is_active
+    }
+
+    /// Waits until there's something to generate.
+    ///
+    /// Returns `false` on a timeout.
+    pub fn wait_until_active( &self, timeout: Duration ) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock();
+        while !state.is_active {
+            if self.condvar.wait_until( &mut state, deadline ).timed_out() {
+                return state.is_active;
+            }
+        }
+
+        true
+    }
+
+    /// Feeds an event from the given source device.
+    ///
+    /// Returns `true` if the event was consumed.
+    pub fn feed( &self, source_id: usize, body: &InputEventBody, source_axes: &[AbsoluteAxisBit] ) -> bool {
+        match (&self.mode, body) {
+            (EmulationMode::StickToMouse { .. }, &InputEventBody::AbsoluteMove { axis, position }) => {
+                if axis != self.x_axis && axis != self.y_axis {
+                    return false;
+                }
+
+                let (min, max) = match find_range( source_axes, axis ) {
+                    Some( range ) => range,
+                    None => return false
+                };
+
+                let value = crate::axis::normalize( position, min, max );
+                let mut state = self.state.lock();
+                let source = state.sources.entry( source_id ).or_default();
+                if axis == self.x_axis {
+                    source.x = value;
+                } else {
+                    source.y = value;
+                }
+
+                self.wake_up( &mut state );
+                true
+            },
+            (&EmulationMode::MouseToStick { sensitivity, .. }, &InputEventBody::RelativeMove { axis, delta }) => {
+                let mut state = self.state.lock();
+                let source = state.sources.entry( source_id ).or_default();
+                match axis {
+                    RelativeAxis::X => source.x = (source.x + delta as f64 * sensitivity).clamp( -1.0, 1.0 ),
+                    RelativeAxis::Y => source.y = (source.y + delta as f64 * sensitivity).clamp( -1.0, 1.0 ),
+                    _ => return false
+                }
+
+                self.wake_up( &mut state );
+                true
+            },
+            _ => false
+        }
+    }
+
+    /// Drops the input of a source device, e.g. when it was disconnected.
+    pub fn reset( &self, source_id: usize ) {
+        let mut state = self.state.lock();
+        if state.sources.remove( &source_id ).is_some() {
+            self.wake_up( &mut state );
+        }
+    }
+
+    /// Generates the events for a single tick which took `elapsed` time.
+    pub fn tick( &self, elapsed: Duration, output: &mut Vec< InputEventBody > ) {
+        let initial_length = output.len();
+        let mut state = self.state.lock();
+        let elapsed = elapsed.as_secs_f64();
+        match self.mode {
+            EmulationMode::StickToMouse { deadzone, speed, acceleration } => {
+                let (mut dx, mut dy) = (0, 0);
+                let mut is_active = false;
+                for source in state.sources.values_mut() {
+                    let (x, y) = crate::axis::radial_deadzone( source.x, source.y, deadzone, 0.0 );
+                    let magnitude = (x * x + y * y).sqrt();
+                    if magnitude == 0.0 {
+                        source.remainder = (0.0, 0.0);
+                        continue;
+                    }
+
+                    is_active = true;
+                    let scale = Curve::Exponent( acceleration ).apply( magnitude.min( 1.0 ) ) / magnitude * speed * elapsed;
+                    let source_dx = source.remainder.0 + x * scale;
+                    let source_dy = source.remainder.1 + y * scale;
+                    source.remainder = (source_dx.fract(), source_dy.fract());
+                    dx += source_dx.trunc() as i32;
+                    dy += source_dy.trunc() as i32;
+                }
+
+                state.is_active = is_active;
+                if dx != 0 {
+                    output.push( InputEventBody::RelativeMove { axis: RelativeAxis::X, delta: dx } );
+                }
+                if dy != 0 {
+                    output.push( InputEventBody::RelativeMove { axis: RelativeAxis::Y, delta: dy } );
+                }
+            },
+            EmulationMode::MouseToStick { recenter, .. } => {
+                let position = self.stick_position( &state );
+                if state.last_emitted != Some( position ) {
+                    state.last_emitted = Some( position );
+                    output.push( InputEventBody::AbsoluteMove { axis: self.x_axis, position: position.0 } );
+                    output.push( InputEventBody::AbsoluteMove { axis: self.y_axis, position: position.1 } );
+                }
+
+                if let Some( recenter ) = recenter {
+                    let factor = if recenter.as_secs_f64() > 0.0 {
+                        (-elapsed / recenter.as_secs_f64()).exp()
+                    } else {
+                        0.0
+                    };
+
+                    for source in state.sources.values_mut() {
+                        // Snapped, since it'd never reach the center otherwise.
+                        source.x = if (source.x * factor).abs() < RECENTER_EPSILON { 0.0 } else { source.x * factor };
+                        source.y = if (source.y * factor).abs() < RECENTER_EPSILON { 0.0 } else { source.y * factor };
+                    }
+                }
+
+                state.is_active = state.last_emitted != Some( self.stick_position( &state ) );
+            }
+        }
+
+        if output.len() > initial_length {
+            output.push( InputEventBody::Flush );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        std::{
+            time::{
+                Duration
+            }
+        },
+        linux_input::{
+            AbsoluteAxis,
+            AbsoluteAxisBit,
+            InputEventBody,
+            RelativeAxis
+        },
+        crate::{
+            config::{
+                EmulationMode
+            }
+        },
+        super::Emulation
+    };
+
+    fn stick_to_mouse() -> Emulation {
+        Emulation::new( &crate::config::Emulation {
+            device: "Pad".into(),
+            target: "Mouse".into(),
+            x_axis: AbsoluteAxis::X,
+            y_axis: AbsoluteAxis::Y,
+            tick: Duration::from_millis( 10 ),
+            mode: EmulationMode::StickToMouse { deadzone: 0.1, speed: 1000.0, acceleration: 1.0 }
+        }, &[] )
+    }
+
+    fn stick_axes() -> Vec< AbsoluteAxisBit > {
+        [AbsoluteAxis::X, AbsoluteAxis::Y].iter().map( |&axis| AbsoluteAxisBit {
+            axis,
+            initial_value: 0,
+            minimum: -100,
+            maximum: 100,
+            noise_threshold: 0,
+            deadzone: 0,
+            resolution: 0
+        }).collect()
+    }
+
+    fn motion( emulation: &Emulation ) -> i32 {
+        let mut output = Vec::new();
+        emulation.tick( Duration::from_millis( 100 ), &mut output );
+        output.iter().map( |body| match *body {
+            InputEventBody::RelativeMove { axis: RelativeAxis::X, delta } => delta,
+            _ => 0
+        }).sum()
+    }
+
+    #[test]
+    fn reset_drops_only_the_input_of_the_unplugged_source() {
+        let emulation = stick_to_mouse();
+        let axes = stick_axes();
+        assert!( emulation.feed( 1, &InputEventBody::AbsoluteMove { axis: AbsoluteAxis::X, position: 100 }, &axes ) );
+        assert!( emulation.feed( 2, &InputEventBody::AbsoluteMove { axis: AbsoluteAxis::X, position: 100 }, &axes ) );
+        let both = motion( &emulation );
+        assert!( both > 0 );
+
+        emulation.reset( 1 );
+        let one = motion( &emulation );
+        assert!( one > 0 && one < both );
+
+        emulation.reset( 2 );
+        assert_eq!( motion( &emulation ), 0 );
+        assert!( !emulation.is_active() );
+    }
+}
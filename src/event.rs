@@ -0,0 +1,370 @@
+use {
+    linux_input::{
+        EventKind,
+        RawInputEvent
+    },
+    crate::{
+        gesture::{
+            Gesture
+        }
+    }
+};
+
+pub const MSC_SCAN: u16 = 0x04;
+pub const REL_HWHEEL: u16 = 0x06;
+pub const REL_HWHEEL_HI_RES: u16 = 0x0c;
+
+/// The relative axes which `linux_input` doesn't know about.
+pub const EXTRA_REL_LIST: &[(&str, u16)] = &[
+    ("HWheel", REL_HWHEEL),
+    ("HWheelHiRes", REL_HWHEEL_HI_RES)
+];
+
+pub const ABS_PRESSURE: u16 = 0x18;
+pub const ABS_MT_SLOT: u16 = 0x2f;
+pub const ABS_MT_POSITION_X: u16 = 0x35;
+pub const ABS_MT_POSITION_Y: u16 = 0x36;
+pub const ABS_MT_TRACKING_ID: u16 = 0x39;
+pub const ABS_MT_PRESSURE: u16 = 0x3a;
+
+/// The absolute axes which `linux_input` doesn't know about.
+pub const EXTRA_ABS_LIST: &[(&str, u16)] = &[
+    ("Pressure", ABS_PRESSURE),
+    ("Distance", 0x19),
+    ("MtSlot", ABS_MT_SLOT),
+    ("MtTouchMajor", 0x30),
+    ("MtTouchMinor", 0x31),
+    ("MtWidthMajor", 0x32),
+    ("MtWidthMinor", 0x33),
+    ("MtOrientation", 0x34),
+    ("MtPositionX", ABS_MT_POSITION_X),
+    ("MtPositionY", ABS_MT_POSITION_Y),
+    ("MtToolType", 0x37),
+    ("MtBlobId", 0x38),
+    ("MtTrackingId", ABS_MT_TRACKING_ID),
+    ("MtPressure", ABS_MT_PRESSURE),
+    ("MtDistance", 0x3b),
+    ("MtToolX", 0x3c),
+    ("MtToolY", 0x3d)
+];
+
+pub const BTN_TOOL_FINGER: u16 = 0x145;
+pub const BTN_TOOL_QUINTTAP: u16 = 0x148;
+pub const BTN_TOUCH: u16 = 0x14a;
+pub const BTN_TOOL_DOUBLETAP: u16 = 0x14d;
+pub const BTN_TOOL_TRIPLETAP: u16 = 0x14e;
+pub const BTN_TOOL_QUADTAP: u16 = 0x14f;
+
+/// The keys which `linux_input` doesn't know about.
+pub const EXTRA_KEY_LIST: &[(&str, u16)] = &[
+    ("ToolPen", 0x140),
+    ("ToolRubber", 0x141),
+    ("ToolFinger", BTN_TOOL_FINGER),
+    ("ToolQuintTap", BTN_TOOL_QUINTTAP),
+    ("Touch", BTN_TOUCH),
+    ("Stylus", 0x14b),
+    ("Stylus2", 0x14c),
+    ("ToolDoubleTap", BTN_TOOL_DOUBLETAP),
+    ("ToolTripleTap", BTN_TOOL_TRIPLETAP),
+    ("ToolQuadTap", BTN_TOOL_QUADTAP)
+];
+
+pub const LED_LIST: &[(&str, u16)] = &[
+    ("NumLock", 0x00),
+    ("CapsLock", 0x01),
+    ("ScrollLock", 0x02),
+    ("Compose", 0x03),
+    ("Kana", 0x04),
+    ("Sleep", 0x05),
+    ("Suspend", 0x06),
+    ("Mute", 0x07),
+    ("Misc", 0x08),
+    ("Mail", 0x09),
+    ("Charging", 0x0a)
+];
+
+pub const SWITCH_LIST: &[(&str, u16)] = &[
+    ("Lid", 0x00),
+    ("TabletMode", 0x01),
+    ("HeadphoneInsert", 0x02),
+    ("RfKillAll", 0x03),
+    ("MicrophoneInsert", 0x04),
+    ("Dock", 0x05),
+    ("LineoutInsert", 0x06),
+    ("JackPhysicalInsert", 0x07),
+    ("VideooutInsert", 0x08),
+    ("CameraLensCover", 0x09),
+    ("KeypadSlide", 0x0a),
+    ("FrontProximity", 0x0b),
+    ("RotateLock", 0x0c),
+    ("LineinInsert", 0x0d),
+    ("MuteDevice", 0x0e),
+    ("PenInserted", 0x0f),
+    ("MachineCover", 0x10)
+];
+
+/// Packs an event kind and code into a single integer, the same way as the `Key_*`, `Abs_*`, etc. constants are.
+pub fn pack_code( kind: EventKind, code: u16 ) -> i32 {
+    code as i32 | (kind.raw() as i32) << 16
+}
+
+/// The `event` object as seen by the scripts.
+pub struct ScriptEvent {
+    pub raw: RawInputEvent,
+    pub device_ref: rhai::ImmutableString,
+    pub device_name: rhai::ImmutableString,
+    /// Set for the synthetic events generated by the gesture recognition.
+    pub gesture: Option< Gesture >,
+    /// Set only for the event the script was called with; its modifications are what gets forwarded.
+    pub is_current: bool
+}
+
+impl Clone for ScriptEvent {
+    /// A copy is a separate event, so modifying it doesn't affect the current event.
+    fn clone( &self ) -> Self {
+        ScriptEvent {
+            raw: self.raw.clone(),
+            device_ref: self.device_ref.clone(),
+            device_name: self.device_name.clone(),
+            gesture: self.gesture,
+            is_current: false
+        }
+    }
+}
+
+impl ScriptEvent {
+    fn kind( &self ) -> EventKind {
+        EventKind::from( self.raw.kind )
+    }
+
+    fn packed_code_if( &self, kind: EventKind ) -> rhai::Dynamic {
+        if self.kind() == kind {
+            pack_code( kind, self.raw.code ).into()
+        } else {
+            ().into()
+        }
+    }
+
+    fn value_if( &self, kind: EventKind ) -> rhai::Dynamic {
+        if self.kind() == kind {
+            self.raw.value.into()
+        } else {
+            ().into()
+        }
+    }
+}
+
+pub fn register_getters( engine: &mut rhai::Engine ) {
+    engine.register_type::< ScriptEvent >();
+    engine.register_get( "timestamp", (|this| this.raw.timestamp.as_f64()) as fn( &mut ScriptEvent ) -> f64 );
+    engine.register_get( "type", (|this| this.raw.kind as i32) as fn( &mut ScriptEvent ) -> i32 );
+    engine.register_get( "kind", (|this|
+        match this.kind() {
+            _ if this.gesture.is_some() => "Gesture".into(),
+            EventKind::Synchronization => "Sync".into(),
+            EventKind::RelativeAxis => "Rel".into(),
+            EventKind::AbsoluteAxis => "Abs".into(),
+            kind => kind.to_string().into()
+        }) as fn( &mut ScriptEvent ) -> rhai::ImmutableString );
+    engine.register_get( "raw_code", (|this| this.raw.code as i32) as fn( &mut ScriptEvent ) -> i32 );
+    engine.register_get( "raw_value", (|this| this.raw.value) as fn( &mut ScriptEvent ) -> i32 );
+    engine.register_get( "code", (|this| pack_code( this.kind(), this.raw.code )) as fn( &mut ScriptEvent ) -> i32 );
+    engine.register_get( "value", (|this| this.raw.value) as fn( &mut ScriptEvent ) -> i32 );
+    engine.register_get( "key", (|this| this.packed_code_if( EventKind::Key )) as fn( &mut ScriptEvent ) -> rhai::Dynamic );
+    engine.register_get( "axis", (|this|
+        match this.kind() {
+            EventKind::AbsoluteAxis | EventKind::RelativeAxis => pack_code( this.kind(), this.raw.code ).into(),
+            _ => ().into()
+        }) as fn( &mut ScriptEvent ) -> rhai::Dynamic );
+    engine.register_get( "position", (|this| this.value_if( EventKind::AbsoluteAxis )) as fn( &mut ScriptEvent ) -> rhai::Dynamic );
+    engine.register_get( "delta", (|this| this.value_if( EventKind::RelativeAxis )) as fn( &mut ScriptEvent ) -> rhai::Dynamic );
+
+    // Autorepeat counts as a press for backwards compatibility; use `repeat` to tell them apart.
+    engine.register_get( "press", (|this| this.kind() == EventKind::Key && this.raw.value != 0) as fn( &mut ScriptEvent ) -> bool );
+    engine.register_get( "release", (|this| this.kind() == EventKind::Key && this.raw.value == 0) as fn( &mut ScriptEvent ) -> bool );
+    engine.register_get( "repeat", (|this| this.kind() == EventKind::Key && this.raw.value == 2) as fn( &mut ScriptEvent ) -> bool );
+
+    // `switch` is a reserved keyword in Rhai, hence the abbreviation.
+    engine.register_get( "sw", (|this| this.packed_code_if( EventKind::Switch )) as fn( &mut ScriptEvent ) -> rhai::Dynamic );
+    engine.register_get( "led", (|this| this.packed_code_if( EventKind::LED )) as fn( &mut ScriptEvent ) -> rhai::Dynamic );
+    engine.register_get( "on", (|this|
+        match this.kind() {
+            EventKind::Switch | EventKind::LED => (this.raw.value != 0).into(),
+            _ => ().into()
+        }) as fn( &mut ScriptEvent ) -> rhai::Dynamic );
+    engine.register_get( "scancode", (|this|
+        if this.kind() == EventKind::Misc && this.raw.code == MSC_SCAN {
+            this.raw.value.into()
+        } else {
+            ().into()
+        }) as fn( &mut ScriptEvent ) -> rhai::Dynamic );
+
+    engine.register_get( "gesture", (|this|
+        match this.gesture {
+            Some( gesture ) => gesture.to_script_map().into(),
+            None => ().into()
+        }) as fn( &mut ScriptEvent ) -> rhai::Dynamic );
+
+    engine.register_get( "device", (|this| this.device_ref.clone()) as fn( &mut ScriptEvent ) -> rhai::ImmutableString );
+    engine.register_get( "device_name", (|this| this.device_name.clone()) as fn( &mut ScriptEvent ) -> rhai::ImmutableString );
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        linux_input::{
+            EventKind,
+            RawInputEvent
+        },
+        super::{
+            MSC_SCAN,
+            ScriptEvent,
+            pack_code,
+            register_getters
+        }
+    };
+
+    const KEY_A: u16 = 30;
+    const REL_Y: u16 = 0x01;
+    const ABS_X: u16 = 0x00;
+    const SW_LID: u16 = 0x00;
+    const LED_CAPSLOCK: u16 = 0x01;
+
+    fn event( kind: EventKind, code: u16, value: i32 ) -> ScriptEvent {
+        let mut raw = RawInputEvent::default();
+        raw.kind = kind.raw();
+        raw.code = code;
+        raw.value = value;
+
+        ScriptEvent {
+            raw,
+            device_ref: "keyboard".into(),
+            device_name: "Test Keyboard".into(),
+            gesture: None,
+            is_current: false
+        }
+    }
+
+    fn get( event: &ScriptEvent, property: &str ) -> rhai::Dynamic {
+        let mut engine = rhai::Engine::new();
+        register_getters( &mut engine );
+
+        let mut scope = rhai::Scope::new();
+        scope.push( "e", event.clone() );
+        engine.eval_with_scope::< rhai::Dynamic >( &mut scope, &format!( "e.{}", property ) ).unwrap()
+    }
+
+    fn get_int( event: &ScriptEvent, property: &str ) -> i32 {
+        get( event, property ).as_int().unwrap()
+    }
+
+    fn get_bool( event: &ScriptEvent, property: &str ) -> bool {
+        get( event, property ).as_bool().unwrap()
+    }
+
+    fn get_string( event: &ScriptEvent, property: &str ) -> String {
+        get( event, property ).into_string().unwrap()
+    }
+
+    fn is_unit( event: &ScriptEvent, property: &str ) -> bool {
+        get( event, property ).is_unit()
+    }
+
+    #[test]
+    fn key_events() {
+        let press = event( EventKind::Key, KEY_A, 1 );
+        let release = event( EventKind::Key, KEY_A, 0 );
+        let repeat = event( EventKind::Key, KEY_A, 2 );
+
+        assert_eq!( get_string( &press, "kind" ), "Key" );
+        assert_eq!( get_int( &press, "key" ), pack_code( EventKind::Key, KEY_A ) );
+        assert_eq!( get_int( &press, "code" ), pack_code( EventKind::Key, KEY_A ) );
+        assert_eq!( get_int( &repeat, "value" ), 2 );
+
+        assert!( get_bool( &press, "press" ) );
+        assert!( !get_bool( &press, "release" ) );
+        assert!( !get_bool( &press, "repeat" ) );
+
+        assert!( !get_bool( &release, "press" ) );
+        assert!( get_bool( &release, "release" ) );
+        assert!( !get_bool( &release, "repeat" ) );
+
+        assert!( get_bool( &repeat, "press" ) );
+        assert!( !get_bool( &repeat, "release" ) );
+        assert!( get_bool( &repeat, "repeat" ) );
+
+        assert!( is_unit( &press, "delta" ) );
+        assert!( is_unit( &press, "position" ) );
+        assert!( is_unit( &press, "sw" ) );
+        assert!( is_unit( &press, "led" ) );
+        assert!( is_unit( &press, "scancode" ) );
+    }
+
+    #[test]
+    fn relative_events() {
+        let motion = event( EventKind::RelativeAxis, REL_Y, -5 );
+
+        assert_eq!( get_string( &motion, "kind" ), "Rel" );
+        // This used to be `()` for relative events.
+        assert_eq!( get_int( &motion, "delta" ), -5 );
+        assert_eq!( get_int( &motion, "value" ), -5 );
+        assert_eq!( get_int( &motion, "code" ), pack_code( EventKind::RelativeAxis, REL_Y ) );
+        assert_eq!( get_int( &motion, "axis" ), pack_code( EventKind::RelativeAxis, REL_Y ) );
+        assert!( is_unit( &motion, "position" ) );
+        assert!( is_unit( &motion, "key" ) );
+        assert!( !get_bool( &motion, "press" ) );
+        assert!( !get_bool( &motion, "release" ) );
+    }
+
+    #[test]
+    fn absolute_events() {
+        let motion = event( EventKind::AbsoluteAxis, ABS_X, 1234 );
+
+        assert_eq!( get_string( &motion, "kind" ), "Abs" );
+        assert_eq!( get_int( &motion, "position" ), 1234 );
+        assert_eq!( get_int( &motion, "value" ), 1234 );
+        assert_eq!( get_int( &motion, "code" ), pack_code( EventKind::AbsoluteAxis, ABS_X ) );
+        assert_eq!( get_int( &motion, "axis" ), pack_code( EventKind::AbsoluteAxis, ABS_X ) );
+        assert!( is_unit( &motion, "delta" ) );
+    }
+
+    #[test]
+    fn switch_events() {
+        let switch = event( EventKind::Switch, SW_LID, 1 );
+
+        assert_eq!( get_string( &switch, "kind" ), EventKind::Switch.to_string() );
+        assert_eq!( get_int( &switch, "sw" ), pack_code( EventKind::Switch, SW_LID ) );
+        assert!( get_bool( &switch, "on" ) );
+        assert!( !get_bool( &event( EventKind::Switch, SW_LID, 0 ), "on" ) );
+        assert!( is_unit( &switch, "led" ) );
+        assert!( is_unit( &switch, "key" ) );
+    }
+
+    #[test]
+    fn led_events() {
+        let led = event( EventKind::LED, LED_CAPSLOCK, 1 );
+
+        assert_eq!( get_string( &led, "kind" ), EventKind::LED.to_string() );
+        assert_eq!( get_int( &led, "led" ), pack_code( EventKind::LED, LED_CAPSLOCK ) );
+        assert!( get_bool( &led, "on" ) );
+        assert!( is_unit( &led, "sw" ) );
+    }
+
+    #[test]
+    fn scancode_events() {
+        let scancode = event( EventKind::Misc, MSC_SCAN, 0x70004 );
+
+        assert_eq!( get_string( &scancode, "kind" ), EventKind::Misc.to_string() );
+        assert_eq!( get_int( &scancode, "scancode" ), 0x70004 );
+        assert!( is_unit( &event( EventKind::Misc, MSC_SCAN + 1, 0x70004 ), "scancode" ) );
+        assert!( is_unit( &event( EventKind::Key, MSC_SCAN, 1 ), "scancode" ) );
+    }
+
+    #[test]
+    fn device() {
+        let press = event( EventKind::Key, KEY_A, 1 );
+
+        assert_eq!( get_string( &press, "device" ), "keyboard" );
+        assert_eq!( get_string( &press, "device_name" ), "Test Keyboard" );
+    }
+}
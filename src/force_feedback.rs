@@ -0,0 +1,330 @@
+use {
+    std::{
+        fs::{
+            self,
+            File
+        },
+        io,
+        os::{
+            unix::{
+                io::{
+                    AsRawFd
+                }
+            }
+        },
+        path::{
+            Path
+        },
+        time::{
+            Duration
+        }
+    }
+};
+
+pub const FF_RUMBLE: u16 = 0x50;
+pub const FF_PERIODIC: u16 = 0x51;
+pub const FF_CONSTANT: u16 = 0x52;
+pub const FF_SPRING: u16 = 0x53;
+pub const FF_FRICTION: u16 = 0x54;
+pub const FF_DAMPER: u16 = 0x55;
+pub const FF_INERTIA: u16 = 0x56;
+pub const FF_RAMP: u16 = 0x57;
+
+pub const FF_GAIN: u16 = 0x60;
+pub const FF_AUTOCENTER: u16 = 0x61;
+
+// These mirror the kernel's `struct ff_effect` and friends from `linux/input.h`.
+//
+// `linux_input` has its own copies, but doesn't export them, so we can't
+// construct or inspect the effects through it.
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct Trigger {
+    pub button: u16,
+    pub interval: u16
+}
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct Replay {
+    pub length: u16,
+    pub delay: u16
+}
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct Envelope {
+    pub attack_length: u16,
+    pub attack_level: u16,
+    pub fade_length: u16,
+    pub fade_level: u16
+}
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct ConstantEffect {
+    pub level: i16,
+    pub envelope: Envelope
+}
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct RampEffect {
+    pub start_level: i16,
+    pub end_level: i16,
+    pub envelope: Envelope
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct PeriodicEffect {
+    pub waveform: u16,
+    pub period: u16,
+    pub magnitude: i16,
+    pub offset: i16,
+    pub phase: u16,
+    pub envelope: Envelope,
+    pub custom_length: u32,
+    pub custom_data: *mut i16
+}
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct ConditionEffect {
+    pub right_saturation: u16,
+    pub left_saturation: u16,
+    pub right_coefficient: i16,
+    pub left_coefficient: i16,
+    pub deadband: u16,
+    pub center: i16
+}
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct RumbleEffect {
+    pub strong_magnitude: u16,
+    pub weak_magnitude: u16
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub union EffectBody {
+    pub constant: ConstantEffect,
+    pub ramp: RampEffect,
+    pub periodic: PeriodicEffect,
+    pub condition: [ConditionEffect; 2],
+    pub rumble: RumbleEffect
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Effect {
+    pub kind: u16,
+    pub id: i16,
+    pub direction: u16,
+    pub trigger: Trigger,
+    pub replay: Replay,
+    pub body: EffectBody
+}
+
+// The only pointer in here is `custom_data`, which is never dereferenced on our side.
+unsafe impl Send for Effect {}
+
+impl Effect {
+    pub fn rumble( strong_magnitude: u16, weak_magnitude: u16, length: Duration ) -> Self {
+        Effect {
+            kind: FF_RUMBLE,
+            id: -1,
+            direction: 0,
+            trigger: Trigger::default(),
+            replay: Replay {
+                length: std::cmp::min( length.as_millis(), 0x7fff ) as u16,
+                delay: 0
+            },
+            body: EffectBody {
+                rumble: RumbleEffect {
+                    strong_magnitude,
+                    weak_magnitude
+                }
+            }
+        }
+    }
+
+    /// Returns a copy of the effect with its strength scaled by `gain`.
+    pub fn scaled( &self, gain: f64 ) -> Self {
+        fn scale_u16( value: u16, gain: f64 ) -> u16 {
+            (value as f64 * gain).round().min( u16::MAX as f64 ) as u16
+        }
+
+        fn scale_i16( value: i16, gain: f64 ) -> i16 {
+            (value as f64 * gain).round().max( i16::MIN as f64 ).min( i16::MAX as f64 ) as i16
+        }
+
+        fn scale_envelope( envelope: &mut Envelope, gain: f64 ) {
+            envelope.attack_level = scale_u16( envelope.attack_level, gain );
+            envelope.fade_level = scale_u16( envelope.fade_level, gain );
+        }
+
+        let mut effect = *self;
+        if gain == 1.0 {
+            return effect;
+        }
+
+        unsafe {
+            match effect.kind {
+                FF_RUMBLE => {
+                    effect.body.rumble.strong_magnitude = scale_u16( effect.body.rumble.strong_magnitude, gain );
+                    effect.body.rumble.weak_magnitude = scale_u16( effect.body.rumble.weak_magnitude, gain );
+                },
+                FF_PERIODIC => {
+                    effect.body.periodic.magnitude = scale_i16( effect.body.periodic.magnitude, gain );
+                    effect.body.periodic.offset = scale_i16( effect.body.periodic.offset, gain );
+                    scale_envelope( &mut effect.body.periodic.envelope, gain );
+                },
+                FF_CONSTANT => {
+                    effect.body.constant.level = scale_i16( effect.body.constant.level, gain );
+                    scale_envelope( &mut effect.body.constant.envelope, gain );
+                },
+                FF_RAMP => {
+                    effect.body.ramp.start_level = scale_i16( effect.body.ramp.start_level, gain );
+                    effect.body.ramp.end_level = scale_i16( effect.body.ramp.end_level, gain );
+                    scale_envelope( &mut effect.body.ramp.envelope, gain );
+                },
+                FF_SPRING | FF_FRICTION | FF_DAMPER | FF_INERTIA => {
+                    for condition in effect.body.condition.iter_mut() {
+                        condition.right_saturation = scale_u16( condition.right_saturation, gain );
+                        condition.left_saturation = scale_u16( condition.left_saturation, gain );
+                        condition.right_coefficient = scale_i16( condition.right_coefficient, gain );
+                        condition.left_coefficient = scale_i16( condition.left_coefficient, gain );
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        effect
+    }
+
+    /// Approximates the effect as a rumble effect, for devices which only support rumble.
+    ///
+    /// The conditions (spring, damper, etc.) depend on the position of the device,
+    /// so those can't be approximated and `None` is returned instead.
+    pub fn approximate_as_rumble( &self ) -> Option< Self > {
+        // The levels are signed, while the rumble magnitudes aren't; only the strength matters here.
+        fn magnitude( level: i16 ) -> u16 {
+            std::cmp::min( (level as i32).abs() * 2, u16::MAX as i32 ) as u16
+        }
+
+        let level = unsafe {
+            match self.kind {
+                FF_RUMBLE => return Some( *self ),
+                FF_PERIODIC => std::cmp::max( magnitude( self.body.periodic.magnitude ), magnitude( self.body.periodic.offset ) ),
+                FF_CONSTANT => magnitude( self.body.constant.level ),
+                FF_RAMP => std::cmp::max( magnitude( self.body.ramp.start_level ), magnitude( self.body.ramp.end_level ) ),
+                _ => return None
+            }
+        };
+
+        let mut effect = *self;
+        effect.kind = FF_RUMBLE;
+        effect.direction = 0;
+        effect.body = EffectBody {
+            rumble: RumbleEffect {
+                strong_magnitude: level,
+                weak_magnitude: level
+            }
+        };
+
+        Some( effect )
+    }
+
+    pub fn kind_name( &self ) -> &'static str {
+        match self.kind {
+            FF_RUMBLE => "rumble",
+            FF_PERIODIC => "periodic",
+            FF_CONSTANT => "constant",
+            FF_SPRING => "spring",
+            FF_FRICTION => "friction",
+            FF_DAMPER => "damper",
+            FF_INERTIA => "inertia",
+            FF_RAMP => "ramp",
+            _ => "unknown"
+        }
+    }
+
+    /// Describes the effect's parameters for the scripts.
+    pub fn fill_script_map( &self, map: &mut rhai::Map ) {
+        map.insert( "effect_type".into(), rhai::ImmutableString::from( self.kind_name() ).into() );
+        map.insert( "direction".into(), (self.direction as i32).into() );
+        map.insert( "length".into(), (self.replay.length as i32).into() );
+        map.insert( "delay".into(), (self.replay.delay as i32).into() );
+
+        unsafe {
+            match self.kind {
+                FF_RUMBLE => {
+                    map.insert( "strong".into(), (self.body.rumble.strong_magnitude as i32).into() );
+                    map.insert( "weak".into(), (self.body.rumble.weak_magnitude as i32).into() );
+                },
+                FF_PERIODIC => {
+                    map.insert( "magnitude".into(), (self.body.periodic.magnitude as i32).into() );
+                    map.insert( "period".into(), (self.body.periodic.period as i32).into() );
+                },
+                FF_CONSTANT => {
+                    map.insert( "level".into(), (self.body.constant.level as i32).into() );
+                },
+                FF_RAMP => {
+                    map.insert( "start_level".into(), (self.body.ramp.start_level as i32).into() );
+                    map.insert( "end_level".into(), (self.body.ramp.end_level as i32).into() );
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A separate handle to a physical device used for uploading and erasing its force feedback effects.
+///
+/// The kernel ties the effects to the file handle through which they were uploaded,
+/// so they're automatically erased once this is dropped.
+///
+/// Playing the effects has to go through the main handle since the events written
+/// into a grabbed device through any other handle are silently ignored.
+pub struct ForceFeedbackDevice {
+    fp: File
+}
+
+impl ForceFeedbackDevice {
+    pub fn open( path: &Path ) -> Result< Self, io::Error > {
+        let fp = fs::OpenOptions::new()
+            .read( true )
+            .write( true )
+            .open( path )?;
+
+        Ok( ForceFeedbackDevice { fp } )
+    }
+
+    /// Uploads a new effect if its `id` is `-1`, otherwise modifies an existing one.
+    ///
+    /// Returns the ID of the effect.
+    pub fn upload( &self, effect: &Effect ) -> Result< i16, io::Error > {
+        let mut effect = *effect;
+        let request = nix::request_code_write!( b'E', 0x80, std::mem::size_of::< Effect >() );
+        let result = unsafe { libc::ioctl( self.fp.as_raw_fd(), request as _, &mut effect as *mut Effect ) };
+        if result < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        Ok( effect.id )
+    }
+
+    pub fn erase( &self, effect_id: i16 ) -> Result< (), io::Error > {
+        let request = nix::request_code_write!( b'E', 0x81, std::mem::size_of::< libc::c_int >() );
+        let result = unsafe { libc::ioctl( self.fp.as_raw_fd(), request as _, effect_id as libc::c_int ) };
+        if result < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        Ok(())
+    }
+}
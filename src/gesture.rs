@@ -0,0 +1,244 @@
+use {
+    linux_input::{
+        AbsoluteAxis,
+        AbsoluteAxisBit
+    },
+    parking_lot::{
+        Mutex
+    },
+    crate::{
+        config::{
+            Gestures
+        },
+        event::{
+            ABS_MT_POSITION_X,
+            ABS_MT_POSITION_Y
+        },
+        multitouch::{
+            Contact
+        }
+    }
+};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GestureKind {
+    Swipe,
+    Pinch,
+    Tap,
+    EdgeSwipe
+}
+
+impl GestureKind {
+    pub fn name( self ) -> &'static str {
+        match self {
+            GestureKind::Swipe => "swipe",
+            GestureKind::Pinch => "pinch",
+            GestureKind::Tap => "tap",
+            GestureKind::EdgeSwipe => "edge-swipe"
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Gesture {
+    pub kind: GestureKind,
+    pub fingers: usize,
+    /// Either `left`, `right`, `up` or `down`, or `in` or `out` for a pinch.
+    pub direction: Option< &'static str >,
+    /// The edge at which an edge swipe started.
+    pub edge: Option< &'static str >,
+    /// How much the fingers spread out during a pinch.
+    pub scale: f64
+}
+
+impl Gesture {
+    pub fn to_script_map( self ) -> rhai::Map {
+        let mut map = rhai::Map::new();
+        map.insert( "type".into(), self.kind.name().into() );
+        map.insert( "fingers".into(), (self.fingers as i32).into() );
+        map.insert( "direction".into(), self.direction.map( |direction| direction.into() ).unwrap_or( ().into() ) );
+        map.insert( "edge".into(), self.edge.map( |edge| edge.into() ).unwrap_or( ().into() ) );
+        map.insert( "scale".into(), self.scale.into() );
+        map
+    }
+}
+
+fn direction_of( dx: f64, dy: f64 ) -> &'static str {
+    if dx.abs() > dy.abs() {
+        if dx > 0.0 { "right" } else { "left" }
+    } else if dy > 0.0 {
+        "down"
+    } else {
+        "up"
+    }
+}
+
+struct Session {
+    started_at: f64,
+    fingers: usize,
+    max_fingers: usize,
+    /// Where the fingers were when the current number of them landed.
+    origin: (f64, f64),
+    origin_spread: f64,
+    max_travel: f64,
+    edge: Option< &'static str >,
+    is_recognized: bool
+}
+
+/// Recognizes the gestures from the multitouch frames of a single device.
+pub struct GestureRecognizer {
+    swipe_distance: f64,
+    pinch_scale: f64,
+    tap_time: f64,
+    tap_distance: f64,
+    edge_margin: f64,
+    x_range: (i32, i32),
+    y_range: (i32, i32),
+    session: Mutex< Option< Session > >
+}
+
+impl GestureRecognizer {
+    /// Returns `None` if the device doesn't report the positions of its contacts.
+    pub fn new( gestures: &Gestures, axes: &[AbsoluteAxisBit] ) -> Option< Self > {
+        let range = |axis| axes.iter().find( |bit| bit.axis == AbsoluteAxis::Other( axis ) ).map( |bit| (bit.minimum, bit.maximum) );
+        Some( GestureRecognizer {
+            swipe_distance: gestures.swipe_distance,
+            pinch_scale: gestures.pinch_scale,
+            tap_time: gestures.tap_time.as_secs_f64() * 1000.0,
+            tap_distance: gestures.tap_distance,
+            edge_margin: gestures.edge_margin,
+            x_range: range( ABS_MT_POSITION_X )?,
+            y_range: range( ABS_MT_POSITION_Y )?,
+            session: Mutex::new( None )
+        })
+    }
+
+    fn edge_of( &self, (x, y): (f64, f64) ) -> Option< &'static str > {
+        if x <= self.edge_margin {
+            Some( "left" )
+        } else if x >= 1.0 - self.edge_margin {
+            Some( "right" )
+        } else if y <= self.edge_margin {
+            Some( "top" )
+        } else if y >= 1.0 - self.edge_margin {
+            Some( "bottom" )
+        } else {
+            None
+        }
+    }
+
+    /// Feeds the contacts at the end of a frame; `now` is the timestamp of the frame in milliseconds.
+    ///
+    /// Every touch produces at most a single gesture.
+    pub fn update( &self, contacts: &[Contact], now: f64 ) -> Option< Gesture > {
+        let mut session = self.session.lock();
+        if contacts.is_empty() {
+            let session = session.take()?;
+            let is_tap = !session.is_recognized && now - session.started_at <= self.tap_time && session.max_travel <= self.tap_distance;
+            if !is_tap {
+                return None;
+            }
+
+            return Some( Gesture {
+                kind: GestureKind::Tap,
+                fingers: session.max_fingers,
+                direction: None,
+                edge: None,
+                scale: 1.0
+            });
+        }
+
+        let count = contacts.len();
+        let points: Vec< (f64, f64) > = contacts.iter().map( |contact| (
+            crate::axis::normalize_unit( contact.x, self.x_range.0, self.x_range.1 ),
+            crate::axis::normalize_unit( contact.y, self.y_range.0, self.y_range.1 )
+        ) ).collect();
+        let centroid = (
+            points.iter().map( |point| point.0 ).sum::< f64 >() / count as f64,
+            points.iter().map( |point| point.1 ).sum::< f64 >() / count as f64
+        );
+        let spread = points.iter().map( |point| (point.0 - centroid.0).hypot( point.1 - centroid.1 ) ).sum::< f64 >() / count as f64;
+
+        let session = match *session {
+            Some( ref mut session ) => session,
+            None => {
+                *session = Some( Session {
+                    started_at: now,
+                    fingers: count,
+                    max_fingers: count,
+                    origin: centroid,
+                    origin_spread: spread,
+                    max_travel: 0.0,
+                    edge: if count == 1 { self.edge_of( centroid ) } else { None },
+                    is_recognized: false
+                });
+                return None;
+            }
+        };
+
+        // The fingers rarely land or lift at the exact same time, so that shouldn't count as movement.
+        if count != session.fingers {
+            session.fingers = count;
+            session.max_fingers = session.max_fingers.max( count );
+            session.origin = centroid;
+            session.origin_spread = spread;
+            session.edge = None;
+            return None;
+        }
+
+        if session.is_recognized {
+            return None;
+        }
+
+        let (dx, dy) = (centroid.0 - session.origin.0, centroid.1 - session.origin.1);
+        let travel = dx.hypot( dy );
+        session.max_travel = session.max_travel.max( travel );
+
+        let gesture = if let Some( edge ) = session.edge {
+            let inward = match edge {
+                "left" => dx,
+                "right" => -dx,
+                "top" => dy,
+                _ => -dy
+            };
+
+            if inward < self.swipe_distance {
+                return None;
+            }
+
+            Gesture {
+                kind: GestureKind::EdgeSwipe,
+                fingers: 1,
+                direction: Some( direction_of( dx, dy ) ),
+                edge: Some( edge ),
+                scale: 1.0
+            }
+        } else if count < 2 {
+            return None;
+        } else {
+            let scale = if session.origin_spread > 0.0 { spread / session.origin_spread } else { 1.0 };
+            if travel < self.swipe_distance && (scale >= self.pinch_scale || scale <= 1.0 / self.pinch_scale) {
+                Gesture {
+                    kind: GestureKind::Pinch,
+                    fingers: count,
+                    direction: Some( if scale > 1.0 { "out" } else { "in" } ),
+                    edge: None,
+                    scale
+                }
+            } else if travel >= self.swipe_distance {
+                Gesture {
+                    kind: GestureKind::Swipe,
+                    fingers: count,
+                    direction: Some( direction_of( dx, dy ) ),
+                    edge: None,
+                    scale
+                }
+            } else {
+                return None;
+            }
+        };
+
+        session.is_recognized = true;
+        Some( gesture )
+    }
+}
@@ -1,8 +1,12 @@
 use {
     std::{
+        cell::{
+            Cell
+        },
         collections::{
             HashMap,
-            HashSet
+            HashSet,
+            VecDeque
         },
         path::{
             Path,
@@ -11,10 +15,14 @@ use {
         sync::{
             atomic::{
                 AtomicBool,
+                AtomicUsize,
                 Ordering
             },
             Arc,
             Weak
+        },
+        time::{
+            Instant
         }
     },
     structopt::{
@@ -32,19 +40,87 @@ use {
         RelativeAxis
     },
     parking_lot::{
-        Condvar,
         Mutex,
         RwLock
     },
     crate::{
         config::{
+            AclEntry,
             Config,
-            DeviceKind
+            DeviceKind,
+            EmergencyAction,
+            ErrorPolicy,
+            ForceFeedbackPolicy,
+            ForceFeedbackTarget,
+            GrabFailurePolicy,
+            ScriptLimits
+        },
+        emulation::{
+            Emulation
+        },
+        event::{
+            ScriptEvent,
+            pack_code
+        },
+        force_feedback::{
+            Effect,
+            ForceFeedbackDevice
+        },
+        gesture::{
+            Gesture,
+            GestureRecognizer
+        },
+        mapping::{
+            AbsoluteMapper
+        },
+        merge::{
+            MergeInput
+        },
+        multitouch::{
+            Contact,
+            Multitouch,
+            TouchEmitter
+        },
+        pipe::{
+            EventPipe
+        },
+        permissions::{
+            OriginalPermissions,
+            RestoreJournal
+        },
+        pointer::{
+            Pointer,
+            PointerMotion
+        },
+        scroll::{
+            ButtonScroll,
+            Wheel
+        },
+        remap::{
+            RemapTable
+        },
+        uinput::{
+            DeviceRequest,
+            VirtualDevice
         }
     }
 };
 
+mod axis;
 mod config;
+mod emulation;
+mod event;
+mod force_feedback;
+mod gesture;
+mod mapping;
+mod merge;
+mod multitouch;
+mod permissions;
+mod pipe;
+mod pointer;
+mod remap;
+mod scroll;
+mod uinput;
 
 #[derive(StructOpt, Debug)]
 enum Opt {
@@ -171,6 +247,18 @@ fn list_devices() -> Vec< PathBuf > {
 }
 
 static RUNNING: AtomicBool = AtomicBool::new( true );
+static NEXT_SOURCE_ID: AtomicUsize = AtomicUsize::new( 1 );
+static NEXT_GRAB_RETRY_ID: AtomicUsize = AtomicUsize::new( 1 );
+
+const RESTORE_JOURNAL_PATH: &str = "/run/inputd-permissions";
+const VIRTUAL_DEVICE_UDEV_TIMEOUT: std::time::Duration = std::time::Duration::from_secs( 5 );
+const GRAB_RETRY_INITIAL_DELAY: std::time::Duration = std::time::Duration::from_millis( 500 );
+const GRAB_RETRY_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs( 30 );
+
+thread_local! {
+    /// The `source_id` of the physical device whose events are processed on the current thread.
+    static CURRENT_SOURCE: Cell< Option< usize > > = const { Cell::new( None ) };
+}
 
 fn get_rdev( path: &Path ) -> Result< (u32, u32), std::io::Error > {
     use std::os::unix::fs::MetadataExt;
@@ -186,13 +274,36 @@ fn get_rdev( path: &Path ) -> Result< (u32, u32), std::io::Error > {
 
 struct DeviceState {
     global_state: Weak< GlobalState >,
+    /// Identifies the device as the source of the keys held down on the virtual devices.
+    source_id: usize,
     path: PathBuf,
     device: Device,
+    force_feedback: Option< ForceFeedbackDevice >,
     info: DeviceInfo,
     matched_filters: Vec< String >,
     scripts: Vec< ScriptInstance >,
-    old_permissions: Option< std::fs::Permissions >,
-    exclusive: bool
+    remaps: Vec< Arc< RemapTable > >,
+    merges: Vec< MergeInput >,
+    emulations: Vec< Arc< Emulation > >,
+    pointer: Option< PointerMotion >,
+    button_scrolls: Vec< Arc< ButtonScroll > >,
+    multitouch: Option< Arc< Multitouch > >,
+    gestures: Option< GestureRecognizer >,
+    absolute_mapper: Option< AbsoluteMapper >,
+    /// The last scancode, held back until it's known whether the key which follows it is remapped.
+    pending_scancode: Mutex< Option< linux_input::InputEvent > >,
+    /// The indexes of the merges which have sent something out in the current frame.
+    merged_targets: Mutex< Vec< usize > >,
+    /// The indexes of the remaps which have sent something out in the current frame.
+    remapped_targets: Mutex< Vec< usize > >,
+    /// Taken once the permissions are restored so that it's never done twice.
+    old_permissions: Mutex< Option< OriginalPermissions > >,
+    emergency_combo: Vec< Key >,
+    on_emergency: EmergencyAction,
+    is_grabbed: AtomicBool,
+    /// Set after an emergency ungrab; the events aren't processed at all until the combo is pressed again.
+    is_paused: AtomicBool,
+    connected_at: Instant
 }
 
 struct VirtualDeviceState {
@@ -200,7 +311,226 @@ struct VirtualDeviceState {
     internal_name: String,
     rdev: (u32, u32),
     path: PathBuf,
-    device: linux_input::VirtualDevice
+    device: VirtualDevice,
+    force_feedback_targets: Vec< ForceFeedbackTarget >,
+    force_feedback_policy: ForceFeedbackPolicy,
+    force_feedback: Mutex< ForceFeedbackState >,
+    mirror_leds_to: Vec< String >,
+    /// Whether the kernel autorepeats the keys, in which case the repeats sent to the device are dropped.
+    has_autorepeat: bool,
+    /// The LEDs as last set by whoever is using the device.
+    leds: Mutex< HashMap< u16, i32 > >,
+    pressed_keys: HeldKeys
+}
+
+/// The keys held down on a virtual device, along with the physical device which pressed them.
+#[derive(Default)]
+struct HeldKeys( Mutex< HashMap< Key, Option< usize > > > );
+
+impl HeldKeys {
+    fn press( &self, key: Key, source: Option< usize > ) {
+        self.0.lock().insert( key, source );
+    }
+
+    fn release( &self, key: Key ) {
+        self.0.lock().remove( &key );
+    }
+
+    /// Forgets either every key or only those pressed by the given physical device, and returns them.
+    fn take( &self, source: Option< usize > ) -> Vec< Key > {
+        let mut pressed_keys = self.0.lock();
+        let keys: Vec< Key > = pressed_keys.iter()
+            .filter( |&(_, &pressed_by)| source.is_none() || pressed_by == source )
+            .map( |(&key, _)| key )
+            .collect();
+
+        for key in &keys {
+            pressed_keys.remove( key );
+        }
+
+        keys
+    }
+}
+
+/// A physical device to which the force feedback of a virtual device is redirected.
+struct ForceFeedbackRedirect {
+    device_state: Arc< DeviceState >,
+    gain: f64
+}
+
+/// A copy of a virtual device's effect uploaded to a physical device.
+struct EffectCopy {
+    device_state: Weak< DeviceState >,
+    /// The `source_id` of the device, so that the copy can be forgotten when it disconnects.
+    source_id: usize,
+    effect_id: i16,
+    gain: f64
+}
+
+/// The force feedback effects of a virtual device and their copies on the physical devices.
+#[derive(Default)]
+struct ForceFeedbackState {
+    effect_map: HashMap< u16, Effect >,
+    /// Contains every effect which was redirected at least once, even if it has no live copies.
+    copies: HashMap< u16, Vec< EffectCopy > >,
+    /// The effects which were started with no explicit length and weren't stopped yet.
+    playing: HashMap< u16, i32 >,
+    gain: Option< i32 >,
+    autocenter: Option< i32 >
+}
+
+/// Translates the effect into something the device supports.
+fn translate_force_feedback_effect( device_state: &DeviceState, effect: &Effect ) -> Option< Effect > {
+    if device_state.supports_force_feedback( effect.kind ) {
+        return Some( *effect );
+    }
+
+    if device_state.supports_force_feedback( crate::force_feedback::FF_RUMBLE ) {
+        if let Some( rumble ) = effect.approximate_as_rumble() {
+            log::debug!( "Translating a {} effect into rumble for '{}'", effect.kind_name(), device_state.info.name );
+            return Some( rumble );
+        }
+    }
+
+    log::warn!( "Device '{}' doesn't support {} effects", device_state.info.name, effect.kind_name() );
+    None
+}
+
+impl ForceFeedbackState {
+    fn copy_on( &self, effect_id: u16, device_state: &Arc< DeviceState > ) -> Option< i16 > {
+        self.copies.get( &effect_id )?.iter()
+            .find( |copy| copy.device_state.as_ptr() == Arc::as_ptr( device_state ) )
+            .map( |copy| copy.effect_id )
+    }
+
+    /// Uploads the effect to a physical device, or updates it if it's already there.
+    fn upload_to( &mut self, redirect: &ForceFeedbackRedirect, effect_id: u16 ) -> Option< i16 > {
+        let device_state = &redirect.device_state;
+        let effect = self.effect_map.get( &effect_id )?;
+        let mut effect = translate_force_feedback_effect( device_state, effect )?.scaled( redirect.gain );
+        effect.id = self.copy_on( effect_id, device_state ).unwrap_or( -1 );
+
+        let target_effect_id = match device_state.upload_force_feedback_effect( &effect ) {
+            Ok( target_effect_id ) => target_effect_id,
+            Err( error ) => {
+                log::warn!( "Failed to upload a force feedback effect on device '{}': {}", device_state.info.name, error );
+                return None;
+            }
+        };
+
+        if effect.id == -1 {
+            self.copies.entry( effect_id ).or_default().push( EffectCopy {
+                device_state: Arc::downgrade( device_state ),
+                source_id: device_state.source_id,
+                effect_id: target_effect_id,
+                gain: redirect.gain
+            });
+        }
+
+        Some( target_effect_id )
+    }
+
+    /// Uploads the effect to the given devices and every device which already has a copy of it.
+    fn upload( &mut self, redirects: Vec< ForceFeedbackRedirect >, effect_id: u16 ) {
+        let mut redirects = redirects;
+        for copy in self.copies.entry( effect_id ).or_default().iter() {
+            if let Some( device_state ) = copy.device_state.upgrade() {
+                if !redirects.iter().any( |redirect| Arc::ptr_eq( &redirect.device_state, &device_state ) ) {
+                    redirects.push( ForceFeedbackRedirect { device_state, gain: copy.gain } );
+                }
+            }
+        }
+
+        for redirect in &redirects {
+            self.upload_to( redirect, effect_id );
+        }
+    }
+
+    fn erase( &mut self, effect_id: u16 ) {
+        self.playing.remove( &effect_id );
+        for copy in self.copies.remove( &effect_id ).unwrap_or_default() {
+            if let Some( device_state ) = copy.device_state.upgrade() {
+                if let Err( error ) = device_state.erase_force_feedback_effect( copy.effect_id ) {
+                    if error.raw_os_error() != Some( libc::ENODEV ) {
+                        log::warn!( "Failed to erase a force feedback effect on device '{}': {}", device_state.info.name, error );
+                    }
+                }
+            }
+        }
+    }
+
+    fn play( &mut self, redirects: &[ForceFeedbackRedirect], effect_id: u16, cycle_count: i32 ) {
+        if self.effect_map.get( &effect_id ).map( |effect| effect.replay.length == 0 ).unwrap_or( false ) {
+            self.playing.insert( effect_id, cycle_count );
+        }
+
+        for redirect in redirects {
+            let device_state = &redirect.device_state;
+            let target_effect_id = match self.copy_on( effect_id, device_state ) {
+                Some( target_effect_id ) => target_effect_id,
+                None => match self.upload_to( redirect, effect_id ) {
+                    Some( target_effect_id ) => target_effect_id,
+                    None => continue
+                }
+            };
+
+            if let Err( error ) = device_state.play_force_feedback_effect( target_effect_id, cycle_count ) {
+                log::warn!( "Failed to enable a force feedback effect on device '{}': {}", device_state.info.name, error );
+            }
+        }
+    }
+
+    /// Forgets the copies on a device which was disconnected.
+    fn forget_device( &mut self, source_id: usize ) {
+        for copies in self.copies.values_mut() {
+            copies.retain( |copy| copy.source_id != source_id );
+        }
+    }
+
+    fn stop( &mut self, effect_id: u16 ) {
+        self.playing.remove( &effect_id );
+        let copies = match self.copies.get_mut( &effect_id ) {
+            Some( copies ) => copies,
+            None => return
+        };
+
+        copies.retain( |copy| {
+            let device_state = match copy.device_state.upgrade() {
+                Some( device_state ) => device_state,
+                None => return false
+            };
+
+            if let Err( error ) = device_state.stop_force_feedback_effect( copy.effect_id ) {
+                if error.raw_os_error() == Some( libc::ENODEV ) {
+                    return false;
+                }
+
+                log::warn!( "Failed to disable a force feedback effect on device '{}': {}", device_state.info.name, error );
+            }
+
+            true
+        });
+    }
+
+    /// Forwards the gain or the autocenter setting to the devices which support it.
+    fn set( &mut self, redirects: &[ForceFeedbackRedirect], code: u16, value: i32 ) {
+        match code {
+            crate::force_feedback::FF_GAIN => self.gain = Some( value ),
+            crate::force_feedback::FF_AUTOCENTER => self.autocenter = Some( value ),
+            _ => return
+        }
+
+        for redirect in redirects {
+            let device_state = &redirect.device_state;
+            if !device_state.supports_force_feedback( code ) {
+                continue;
+            }
+
+            if let Err( error ) = device_state.emit_force_feedback( code, value ) {
+                log::warn!( "Failed to set the force feedback {} on device '{}': {}", if code == crate::force_feedback::FF_GAIN { "gain" } else { "autocenter" }, device_state.info.name, error );
+            }
+        }
+    }
 }
 
 enum AnyDeviceState {
@@ -215,33 +545,73 @@ impl AnyDeviceState {
                 log::trace!( ">> {:?}: {:?}", device_state.path, body.as_ref() );
                 device_state.device.emit( body )
             },
-            AnyDeviceState::Virtual( ref virtual_device_state ) => {
-                log::trace!( ">> {:?}: {:?}", virtual_device_state.path, body.as_ref() );
-                virtual_device_state.device.emit( body )
-            }
+            AnyDeviceState::Virtual( ref virtual_device_state ) => virtual_device_state.emit( body.as_ref() )
         }
     }
 }
 
+/// A freshly found device which matches at least one device filter.
+struct MatchedDevice {
+    path: PathBuf,
+    device: Device,
+    info: DeviceInfo,
+    matched_filters: Vec< String >,
+    exclusive: bool,
+    chmod: Option< u16 >,
+    owner: Option< u32 >,
+    group: Option< u32 >,
+    acl: Vec< AclEntry >,
+    emergency_combo: Vec< Key >,
+    on_emergency: EmergencyAction
+}
+
 struct GlobalState {
     config: Config,
     scripts: Vec< Arc< Script > >,
+    remaps: Vec< Arc< RemapTable > >,
+    emulations: Vec< Arc< Emulation > >,
+    pointers: Vec< Arc< Pointer > >,
+    button_scrolls: Vec< Arc< ButtonScroll > >,
     devices_by_internal_name: RwLock< HashMap< String, Vec< Arc< DeviceState > > > >,
     virtual_device_by_rdev: RwLock< HashMap< (u32, u32), Arc< VirtualDeviceState > > >,
     virtual_device_by_internal_name: RwLock< HashMap< String, Arc< VirtualDeviceState > > >,
+    journal: RestoreJournal,
+    /// The grab retries in flight, keyed by the device's path.
+    ///
+    /// Each retry has its own id, so that a stale retry of a replugged device can tell it was superseded.
+    grab_retries: Mutex< HashMap< PathBuf, usize > >
 }
 
 fn find_device_for_ff( global_state: &GlobalState, target: &str ) -> Option< Arc< DeviceState > > {
     let devices_by_internal_name = global_state.devices_by_internal_name.read();
-    let device_states = match devices_by_internal_name.get( target ) {
-        Some( device_states ) => device_states,
-        None => return None
-    };
+    let device_states = devices_by_internal_name.get( target )?;
 
-    if let Some( device_state ) = device_states.iter().find( |device_state| !device_state.info.force_feedback_bits.is_empty() ) {
-        Some( device_state.clone() )
-    } else {
-        None
+    device_states.iter().find( |device_state| device_state.force_feedback.is_some() ).cloned()
+}
+
+fn find_devices_for_ff( global_state: &GlobalState, targets: &[ForceFeedbackTarget], policy: ForceFeedbackPolicy ) -> Vec< ForceFeedbackRedirect > {
+    let devices_by_internal_name = global_state.devices_by_internal_name.read();
+    let mut redirects: Vec< ForceFeedbackRedirect > = Vec::new();
+    for target in targets {
+        let device_states = match devices_by_internal_name.get( &target.device ) {
+            Some( device_states ) => device_states,
+            None => continue
+        };
+
+        for device_state in device_states {
+            if device_state.force_feedback.is_some() && !redirects.iter().any( |redirect| Arc::ptr_eq( &redirect.device_state, device_state ) ) {
+                redirects.push( ForceFeedbackRedirect {
+                    device_state: device_state.clone(),
+                    gain: target.gain
+                });
+            }
+        }
+    }
+
+    match policy {
+        ForceFeedbackPolicy::First => redirects.into_iter().take( 1 ).collect(),
+        ForceFeedbackPolicy::All => redirects,
+        ForceFeedbackPolicy::MostRecent => redirects.into_iter().max_by_key( |redirect| redirect.device_state.connected_at ).into_iter().collect()
     }
 }
 
@@ -254,12 +624,164 @@ impl Drop for VirtualDeviceState {
     }
 }
 
+fn force_feedback_request( kind: &str, effect_id: Option< u16 >, effect: Option< &Effect > ) -> rhai::Map {
+    let mut request = rhai::Map::new();
+    request.insert( "kind".into(), rhai::ImmutableString::from( kind ).into() );
+    if let Some( effect_id ) = effect_id {
+        request.insert( "effect_id".into(), (effect_id as i32).into() );
+    }
+    if let Some( effect ) = effect {
+        effect.fill_script_map( &mut request );
+    }
+
+    request
+}
+
 impl VirtualDeviceState {
-    fn force_feedback_thread_main( &self, redirect_to: String ) {
-        let mut effect_map = HashMap::new();
-        let mut device_by_effect = HashMap::new();
+    fn emit( &self, body: &linux_input::InputEventBody ) -> Result< (), std::io::Error > {
+        match *body {
+            linux_input::InputEventBody::KeyPress( key ) => self.pressed_keys.press( key, CURRENT_SOURCE.with( |source| source.get() ) ),
+            linux_input::InputEventBody::KeyRelease( key ) => self.pressed_keys.release( key ),
+            linux_input::InputEventBody::Other { kind: EventKind::Key, value: 2, .. } if self.has_autorepeat => return Ok(()),
+            _ => {}
+        }
+
+        log::trace!( ">> {:?}: {:?}", self.path, body );
+        self.device.emit( body )
+    }
+
+    /// Releases the keys which are still held down on the device, either every one of them
+    /// or only those pressed by the given physical device.
+    fn release_keys( &self, source: Option< usize > ) {
+        let keys = self.pressed_keys.take( source );
+        if keys.is_empty() {
+            return;
+        }
+
+        log::info!( "Releasing {} held key(s) on '{}'", keys.len(), self.internal_name );
+        for key in keys {
+            if let Err( error ) = self.device.emit( linux_input::InputEventBody::KeyRelease( key ) ) {
+                log::warn!( "Failed to release a key on '{}': {}", self.internal_name, error );
+            }
+        }
+
+        let _ = self.device.emit( linux_input::InputEventBody::Flush );
+    }
+
+    /// Runs the `on-force-feedback` script, if there is one.
+    ///
+    /// Returns `true` if the script has handled the request by itself and it shouldn't be redirected.
+    fn run_force_feedback_hook( &self, hook: Option< &ScriptInstance >, request: rhai::Map ) -> bool {
+        let hook = match hook {
+            Some( hook ) if !hook.is_disabled() => hook,
+            _ => return false
+        };
+
+        if !hook.eval_force_feedback( request ) {
+            log::warn!( "Force feedback requests for '{}' will now bypass the script", self.internal_name );
+            return false;
+        }
+
+        hook.was_handled()
+    }
+
+    fn force_feedback_devices( &self, global_state: &GlobalState ) -> Vec< ForceFeedbackRedirect > {
+        find_devices_for_ff( global_state, &self.force_feedback_targets, self.force_feedback_policy )
+    }
+
+    fn on_force_feedback_device_disconnected( &self, source_id: usize ) {
+        self.force_feedback.lock().forget_device( source_id );
+    }
+
+    /// Eagerly uploads all of the redirected effects to a freshly connected device, if it's one of the targets.
+    fn on_force_feedback_device_connected( &self, device_state: &Arc< DeviceState > ) {
+        if !self.force_feedback_targets.iter().any( |target| device_state.matched_filters.contains( &target.device ) ) {
+            return;
+        }
+
+        let global_state = match self.global_state.upgrade() {
+            Some( global_state ) => global_state,
+            None => return
+        };
+
+        let redirect = match self.force_feedback_devices( &global_state ).into_iter().find( |redirect| Arc::ptr_eq( &redirect.device_state, device_state ) ) {
+            Some( redirect ) => redirect,
+            None => return
+        };
+
+        let mut force_feedback = self.force_feedback.lock();
+        let redirects = std::slice::from_ref( &redirect );
+        if let Some( gain ) = force_feedback.gain {
+            force_feedback.set( redirects, crate::force_feedback::FF_GAIN, gain );
+        }
+        if let Some( autocenter ) = force_feedback.autocenter {
+            force_feedback.set( redirects, crate::force_feedback::FF_AUTOCENTER, autocenter );
+        }
+
+        let effect_ids: Vec< u16 > = force_feedback.copies.keys().cloned().collect();
+        if effect_ids.is_empty() {
+            return;
+        }
+
+        for &effect_id in &effect_ids {
+            force_feedback.upload_to( &redirect, effect_id );
+        }
+
+        // Effects with no explicit length play until stopped, so restart those which are supposed to be playing.
+        let playing: Vec< (u16, i32) > = force_feedback.playing.iter().map( |(&effect_id, &cycle_count)| (effect_id, cycle_count) ).collect();
+        for (effect_id, cycle_count) in playing {
+            force_feedback.play( redirects, effect_id, cycle_count );
+        }
+
+        log::info!( "Uploaded {} force feedback effect(s) of '{}' to '{}'", effect_ids.len(), self.internal_name, device_state.info.name );
+    }
+
+    /// Returns the physical devices to which the LEDs of this device are mirrored.
+    fn led_devices( &self, global_state: &GlobalState ) -> Vec< Arc< DeviceState > > {
+        let devices_by_internal_name = global_state.devices_by_internal_name.read();
+        let mut devices: Vec< Arc< DeviceState > > = Vec::new();
+        for device_states in devices_by_internal_name.values() {
+            for device_state in device_states {
+                if device_state.mirrors_leds_of( self ) && !devices.iter().any( |device| Arc::ptr_eq( device, device_state ) ) {
+                    devices.push( device_state.clone() );
+                }
+            }
+        }
+
+        devices
+    }
+
+    /// Brings the LEDs of a freshly connected device in sync with this device.
+    fn on_led_device_connected( &self, device_state: &DeviceState ) {
+        if !device_state.mirrors_leds_of( self ) {
+            return;
+        }
+
+        let leds: Vec< (u16, i32) > = self.leds.lock().iter().map( |(&code, &value)| (code, value) ).collect();
+        for (code, value) in leds {
+            if let Err( error ) = device_state.set_led( code, value != 0 ) {
+                log::warn!( "Failed to set a LED on device '{}': {}", device_state.info.name, error );
+            }
+        }
+    }
+
+    fn on_led( &self, global_state: &GlobalState, code: u16, value: i32 ) {
+        self.leds.lock().insert( code, value );
+        for device_state in self.led_devices( global_state ) {
+            if let Err( error ) = device_state.set_led( code, value != 0 ) {
+                log::warn!( "Failed to mirror a LED of '{}' to device '{}': {}", self.internal_name, device_state.info.name, error );
+            }
+        }
+    }
+
+    fn set_led( &self, code: u16, is_on: bool ) -> Result< (), std::io::Error > {
+        self.device.emit( linux_input::InputEventBody::Other { kind: EventKind::LED, code, value: is_on as i32 } )?;
+        self.device.emit( linux_input::InputEventBody::Flush )
+    }
+
+    fn thread_main( &self, hook: Option< ScriptInstance > ) {
         while RUNNING.load( Ordering::Relaxed ) {
-            let event = match self.device.poll_force_feedback( None ).unwrap() {
+            let event = match self.device.poll( None ).unwrap() {
                 Some( event ) => event,
                 None => continue
             };
@@ -270,197 +792,536 @@ impl VirtualDeviceState {
             };
 
             match event {
-                linux_input::ForceFeedbackRequest::Upload( request ) => {
-                    effect_map.insert( request.effect_id(), request.raw_effect() );
-
-                    let device_state = if let Some( device_state ) = find_device_for_ff( &global_state, &redirect_to ) {
-                        device_state
-                    } else {
-                        continue;
-                    };
-
-                    match device_state.device.upload_force_feedback_effect( request.raw_effect() ) {
-                        Ok( target_effect_id ) => {
-                            device_by_effect.insert( request.effect_id(), (Arc::downgrade( &device_state ), target_effect_id) );
-                        },
-                        Err( error ) => {
-                            log::warn!( "Failed to upload a force feedback effect on device '{}': {}", device_state.info.name, error );
-                        }
+                DeviceRequest::Upload( request ) => {
+                    let effect_id = request.effect_id();
+                    let effect = request.effect();
+                    self.force_feedback.lock().effect_map.insert( effect_id, effect );
+
+                    let handled = self.run_force_feedback_hook( hook.as_ref(), force_feedback_request( "upload", Some( effect_id ), Some( &effect ) ) );
+                    if !handled && !self.force_feedback_targets.is_empty() {
+                        let devices = self.force_feedback_devices( &global_state );
+                        self.force_feedback.lock().upload( devices, effect_id );
                     }
 
                     if let Err( error ) = request.complete() {
                         log::error!( "Failed to complete a force feedback upload request: {}", error );
                     }
                 },
-                linux_input::ForceFeedbackRequest::Erase( request ) => {
-                    effect_map.remove( &request.effect_id() );
+                DeviceRequest::Erase( request ) => {
+                    let effect_id = request.effect_id();
+                    let effect = self.force_feedback.lock().effect_map.remove( &effect_id );
+                    self.run_force_feedback_hook( hook.as_ref(), force_feedback_request( "erase", Some( effect_id ), effect.as_ref() ) );
 
-                    let (device_state, target_effect_id) = match device_by_effect.remove( &request.effect_id() ) {
-                        Some( effect ) => effect,
-                        None => continue
-                    };
-                    let device_state = match device_state.upgrade() {
-                        Some( device_state ) => device_state,
-                        None => continue
-                    };
-
-                    if let Err( error ) = device_state.device.erase_force_feedback_effect( target_effect_id ) {
-                        if error.as_errno().map( |error| error == nix::errno::Errno::ENODEV ).unwrap_or( false ) {
-                            device_by_effect.remove( &request.effect_id() );
-                        } else {
-                            log::warn!( "Failed to erase a force feedback effect on device '{}': {}", device_state.info.name, error );
-                        }
-                    }
+                    // The redirected copies are always erased, even if the script handled the request itself.
+                    self.force_feedback.lock().erase( effect_id );
 
                     if let Err( error ) = request.complete() {
                         log::error!( "Failed to complete a force feedback erase request: {}", error );
                     }
                 },
-                linux_input::ForceFeedbackRequest::Enable { effect_id, cycle_count } => {
-                    let mut found = false;
-                    if let Some( (device_state, target_effect_id) ) = device_by_effect.get( &effect_id ) {
-                        if let Some( device_state ) = device_state.upgrade() {
-                            if let Err( error ) = device_state.device.enable_force_feedback_effect( *target_effect_id, cycle_count ) {
-                                if error.raw_os_error() == Some( libc::ENODEV ) {
-                                    device_by_effect.remove( &effect_id );
-                                } else {
-                                    log::warn!( "Failed to enable a force feedback effect on device '{}': {}", device_state.info.name, error );
-                                    found = true;
-                                }
-                            } else {
-                                found = true;
-                            }
-                        }
+                DeviceRequest::Enable { effect_id, cycle_count } => {
+                    let mut request = force_feedback_request( "play", Some( effect_id ), self.force_feedback.lock().effect_map.get( &effect_id ) );
+                    request.insert( "cycle_count".into(), cycle_count.into() );
+                    if self.run_force_feedback_hook( hook.as_ref(), request ) || self.force_feedback_targets.is_empty() {
+                        continue;
                     }
 
-                    if !found {
-                        if let Some( raw_effect ) = effect_map.get( &effect_id ) {
-                            if let Some( device_state ) = find_device_for_ff( &global_state, &redirect_to ) {
-                                match device_state.device.upload_force_feedback_effect( *raw_effect ) {
-                                    Ok( target_effect_id ) => {
-                                        device_by_effect.insert( effect_id, (Arc::downgrade( &device_state ), target_effect_id) );
-                                        if let Err( error ) = device_state.device.enable_force_feedback_effect( target_effect_id, cycle_count ) {
-                                            log::warn!( "Failed to enable a force feedback effect on device '{}': {}", device_state.info.name, error );
-                                        }
-                                    },
-                                    Err( error ) => {
-                                        log::warn!( "Failed to upload a force feedback effect on device '{}': {}", device_state.info.name, error );
-                                    }
-                                }
-                            }
-                        }
+                    let devices = self.force_feedback_devices( &global_state );
+                    self.force_feedback.lock().play( &devices, effect_id, cycle_count );
+                },
+                DeviceRequest::Disable { effect_id } => {
+                    let request = force_feedback_request( "stop", Some( effect_id ), self.force_feedback.lock().effect_map.get( &effect_id ) );
+                    if self.run_force_feedback_hook( hook.as_ref(), request ) {
+                        continue;
                     }
+
+                    self.force_feedback.lock().stop( effect_id );
                 },
-                linux_input::ForceFeedbackRequest::Disable { effect_id } => {
-                    if let Some( (device_state, target_effect_id) ) = device_by_effect.get( &effect_id ) {
-                        if let Some( device_state ) = device_state.upgrade() {
-                            if let Err( error ) = device_state.device.disable_force_feedback_effect( *target_effect_id ) {
-                                if error.raw_os_error() == Some( libc::ENODEV ) {
-                                    device_by_effect.remove( &effect_id );
-                                } else {
-                                    log::warn!( "Failed to disable a force feedback effect on device '{}': {}", device_state.info.name, error );
-                                }
-                            }
+                DeviceRequest::Led { code, value } => self.on_led( &global_state, code, value ),
+                DeviceRequest::Other { code, value } => {
+                    let mut request = match code {
+                        crate::force_feedback::FF_GAIN => force_feedback_request( "gain", None, None ),
+                        crate::force_feedback::FF_AUTOCENTER => force_feedback_request( "autocenter", None, None ),
+                        _ => {
+                            let mut request = force_feedback_request( "other", None, None );
+                            request.insert( "code".into(), (code as i32).into() );
+                            request
+                        }
+                    };
+                    request.insert( "value".into(), value.into() );
+                    if self.run_force_feedback_hook( hook.as_ref(), request ) || self.force_feedback_targets.is_empty() {
+                        continue;
+                    }
+
+                    let devices = self.force_feedback_devices( &global_state );
+                    self.force_feedback.lock().set( &devices, code, value );
+                }
+            }
+        }
+    }
+}
+
+impl DeviceState {
+    fn upload_force_feedback_effect( &self, effect: &Effect ) -> Result< i16, std::io::Error > {
+        match self.force_feedback {
+            Some( ref force_feedback ) => force_feedback.upload( effect ),
+            None => Err( std::io::Error::from_raw_os_error( libc::ENOTSUP ) )
+        }
+    }
+
+    fn erase_force_feedback_effect( &self, effect_id: i16 ) -> Result< (), std::io::Error > {
+        match self.force_feedback {
+            Some( ref force_feedback ) => force_feedback.erase( effect_id ),
+            None => Err( std::io::Error::from_raw_os_error( libc::ENOTSUP ) )
+        }
+    }
+
+    fn supports_force_feedback( &self, code: u16 ) -> bool {
+        self.force_feedback.is_some() && self.info.force_feedback_bits.iter().any( |bit| bit.raw() == code )
+    }
+
+    fn emit_force_feedback( &self, code: u16, value: i32 ) -> Result< (), std::io::Error > {
+        self.device.emit( linux_input::InputEventBody::Other {
+            kind: EventKind::ForceFeedback,
+            code,
+            value
+        })
+    }
+
+    /// Whether any of the merges, remaps or scripts of this device send events to the given virtual device.
+    fn feeds( &self, target: &str ) -> bool {
+        self.merges.iter().any( |merge| merge.target == target ) ||
+        self.remaps.iter().any( |remap| remap.target == target ) ||
+        self.scripts.iter().any( |script| {
+            script.script.default_target.as_deref() == Some( target ) || script.script.passthrough_to.as_deref() == Some( target )
+        })
+    }
+
+    /// Whether the LEDs of the given virtual device should be mirrored to this device.
+    fn mirrors_leds_of( &self, virtual_device: &VirtualDeviceState ) -> bool {
+        if !self.is_grabbed.load( Ordering::Relaxed ) {
+            return false;
+        }
+
+        if virtual_device.mirror_leds_to.is_empty() {
+            self.info.guess_type() == Some( DeviceKind::Keyboard ) && self.feeds( &virtual_device.internal_name )
+        } else {
+            virtual_device.mirror_leds_to.iter().any( |target| self.matched_filters.contains( target ) )
+        }
+    }
+
+    fn set_led( &self, code: u16, is_on: bool ) -> Result< (), std::io::Error > {
+        // The LEDs are updated immediately, so there's no need to flush.
+        self.device.emit( linux_input::InputEventBody::Other {
+            kind: EventKind::LED,
+            code,
+            value: is_on as i32
+        })
+    }
+
+    fn play_force_feedback_effect( &self, effect_id: i16, cycle_count: i32 ) -> Result< (), std::io::Error > {
+        self.emit_force_feedback( effect_id as u16, cycle_count )
+    }
+
+    fn stop_force_feedback_effect( &self, effect_id: i16 ) -> Result< (), std::io::Error > {
+        self.play_force_feedback_effect( effect_id, 0 )
+    }
+
+    fn send_event< T >( &self, target: &str, body: T ) where T: AsRef< linux_input::InputEventBody > {
+        let global_state = match self.global_state.upgrade() {
+            Some( global_state ) => global_state,
+            None => return
+        };
+
+        if let Some( device ) = global_state.lookup_device_by_internal_name( target ) {
+            if let Err( error ) = device.emit( body ) {
+                log::error!( "Failed to send an event to '{}': {}", target, error );
+            }
+        }
+    }
+
+    /// Feeds the event into the stick-to-mouse and mouse-to-stick emulations.
+    ///
+    /// Returns `true` if the event was consumed by one of them.
+    fn apply_emulations( &self, event: &linux_input::InputEvent ) -> bool {
+        self.emulations.iter().any( |emulation| emulation.feed( self.source_id, &event.body, &self.info.absolute_axis_bits ) )
+    }
+
+    /// Runs the event through the `[[button-scroll]]` entries.
+    ///
+    /// Returns `true` if the event was consumed by one of them.
+    fn apply_button_scrolls( &self, event: &linux_input::InputEvent, buffer: &mut Vec< linux_input::InputEventBody > ) -> bool {
+        for button_scroll in &self.button_scrolls {
+            let is_consumed = button_scroll.translate( self.source_id, &event.body, buffer );
+            for body in buffer.drain( .. ) {
+                self.send_event( &button_scroll.target, body );
+            }
+
+            if is_consumed {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Runs the event through the `[[merge]]` mappings.
+    ///
+    /// The merges take precedence over the remaps and the scripts, so the merged
+    /// keys and axes are never seen by either of them.
+    ///
+    /// Returns `true` if the event was fully handled and shouldn't be processed any further.
+    fn apply_merges( &self, event: &linux_input::InputEvent, buffer: &mut Vec< linux_input::InputEventBody > ) -> bool {
+        if self.merges.is_empty() {
+            return false;
+        }
+
+        // Events which aren't merged are dropped unless something else is interested in them.
+        let is_unclaimed = self.scripts.is_empty() && self.remaps.is_empty();
+        if event.body == linux_input::InputEventBody::Flush {
+            let mut targets: Vec< &str > = self.merged_targets.lock().drain( .. ).map( |index| self.merges[ index ].target.as_str() ).collect();
+            targets.sort();
+            targets.dedup();
+            for target in targets {
+                self.send_event( target, linux_input::InputEventBody::Flush );
+            }
+            return is_unclaimed;
+        }
+
+        for (index, merge) in self.merges.iter().enumerate() {
+            if merge.translate( &event.body, buffer ) {
+                for body in buffer.drain( .. ) {
+                    self.send_event( &merge.target, body );
+                }
+
+                let mut merged_targets = self.merged_targets.lock();
+                if !merged_targets.contains( &index ) {
+                    merged_targets.push( index );
+                }
+
+                return true;
+            }
+        }
+
+        is_unclaimed
+    }
+
+    /// Runs the event through the `[[remap]]` tables.
+    ///
+    /// Returns `true` if the event was fully handled and shouldn't be passed to the scripts.
+    fn apply_remaps( &self, event: &linux_input::InputEvent, buffer: &mut Vec< linux_input::InputEventBody > ) -> bool {
+        if self.remaps.is_empty() {
+            return false;
+        }
+
+        // Without any scripts the events which aren't remapped go to the first remap's target.
+        let passthrough_target = if self.scripts.is_empty() { Some( self.remaps[ 0 ].target.as_str() ) } else { None };
+
+        // The remapped keys are sent as they come, but their frame is only closed along with the source's frame.
+        if event.body == linux_input::InputEventBody::Flush {
+            let mut targets: Vec< &str > = self.remapped_targets.lock().drain( .. ).map( |index| self.remaps[ index ].target.as_str() ).collect();
+            targets.extend( passthrough_target );
+            targets.sort();
+            targets.dedup();
+            for target in targets {
+                self.send_event( target, event );
+            }
+
+            return passthrough_target.is_some();
+        }
+
+        for (index, remap) in self.remaps.iter().enumerate() {
+            if remap.translate( self.source_id, &event.body, buffer ) {
+                for body in buffer.drain( .. ) {
+                    self.send_event( &remap.target, body );
+                }
+
+                let mut remapped_targets = self.remapped_targets.lock();
+                if !remapped_targets.contains( &index ) {
+                    remapped_targets.push( index );
+                }
+
+                return true;
+            }
+        }
+
+        match passthrough_target {
+            Some( target ) => {
+                self.send_event( target, event );
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Passes the event through the built-in modes, the pointer profile, and then the rest.
+    fn dispatch_event( &self, event: linux_input::InputEvent, translated: &mut Vec< linux_input::InputEvent >, buffer: &mut Vec< linux_input::InputEventBody > ) {
+        if self.apply_emulations( &event ) || self.apply_button_scrolls( &event, buffer ) {
+            return;
+        }
+
+        match self.pointer {
+            Some( ref pointer ) if pointer.translate( &event, translated ) => {
+                for event in translated.drain( .. ) {
+                    self.process_event( event, buffer );
+                }
+            },
+            _ => self.process_event( event, buffer )
+        }
+    }
+
+    /// Forwards the scancodes only along with the keys which aren't remapped, since they'd be wrong for the remapped ones.
+    fn process_event( &self, event: linux_input::InputEvent, buffer: &mut Vec< linux_input::InputEventBody > ) {
+        if self.remaps.is_empty() {
+            return self.route_event( event, buffer );
+        }
+
+        if crate::remap::is_scancode( &event.body ) {
+            *self.pending_scancode.lock() = Some( event );
+            return;
+        }
+
+        let scancode = self.pending_scancode.lock().take();
+        if let Some( scancode ) = scancode {
+            if !self.remaps.iter().any( |remap| remap.remaps( &event.body ) ) {
+                self.route_event( scancode, buffer );
+            }
+        }
+
+        self.route_event( event, buffer );
+    }
+
+    /// Passes the event through the merges, the remaps and the scripts.
+    fn route_event( &self, event: linux_input::InputEvent, buffer: &mut Vec< linux_input::InputEventBody > ) {
+        // Just like with the kernel's autorepeat pressing any other key stops the repeat.
+        if let linux_input::InputEventBody::KeyPress( _ ) = event.body {
+            for remap in &self.remaps {
+                remap.reset( self.source_id );
+            }
+        }
+
+        if self.apply_merges( &event, buffer ) || self.apply_remaps( &event, buffer ) {
+            return;
+        }
+
+        for script in &self.scripts {
+            if script.is_disabled() {
+                if let ErrorPolicy::Passthrough( ref target ) = script.script.on_error_limit {
+                    script.script_state.lock().send_event( target, event.clone() );
+                }
+                continue;
+            }
+
+            let mut state = script.script_state.lock();
+            state.event = event.clone();
+            std::mem::drop( state );
+
+            if !script.eval() {
+                self.on_script_disabled( script );
+            }
+        }
+    }
+
+    fn on_gesture( &self, gesture: Gesture, timestamp: linux_input::Timestamp ) {
+        log::debug!( "Gesture on '{}': {:?}", self.info.name, gesture );
+        for script in &self.scripts {
+            if script.is_disabled() {
+                continue;
+            }
+
+            if !script.eval_gesture( gesture, timestamp ) {
+                self.on_script_disabled( script );
+            }
+        }
+    }
+
+    fn on_script_disabled( &self, script: &ScriptInstance ) {
+        // Whatever the script was holding down won't ever be released by it.
+        if let Some( global_state ) = self.global_state.upgrade() {
+            global_state.release_held_keys( Some( self.source_id ) );
+        }
+
+        match script.script.on_error_limit {
+            ErrorPolicy::Ungrab => {
+                if !self.is_grabbed.swap( false, Ordering::SeqCst ) {
+                    return;
+                }
+
+                if let Err( error ) = self.device.release() {
+                    log::error!( "Failed to turn off the exclusive mode for '{}': {}", self.info.name, error );
+                } else {
+                    log::warn!( "Exclusive mode turned off for '{}'", self.info.name );
+                }
+            },
+            ErrorPolicy::Passthrough( ref target ) => {
+                log::warn!( "Events from '{}' will now be passed through to '{}'", self.info.name, target );
+            }
+        }
+    }
+
+    /// Restores the permissions of the device node if we've changed them.
+    fn restore_permissions( &self, global_state: &GlobalState ) {
+        if let Some( old_permissions ) = self.old_permissions.lock().take() {
+            let _ = old_permissions.restore();
+            global_state.journal.remove( self.source_id );
+        }
+    }
+
+    fn on_emergency_combo( &self ) {
+        let global_state = self.global_state.upgrade();
+        match self.on_emergency {
+            EmergencyAction::Exit => {
+                log::warn!( "Emergency exit triggered!" );
+                RUNNING.store( false, Ordering::SeqCst );
+
+                if let Some( global_state ) = global_state {
+                    global_state.release_held_keys( None );
+                    global_state.restore_permissions();
+                }
+
+                std::process::exit( 1 );
+            },
+            EmergencyAction::Ungrab => {
+                if self.is_paused.load( Ordering::SeqCst ) {
+                    // The permissions which were restored on the ungrab aren't changed back.
+                    match self.device.grab() {
+                        Ok(()) => {
+                            log::warn!( "Emergency combo pressed again for '{}'; exclusive mode turned back on", self.info.name );
+                            self.is_grabbed.store( true, Ordering::SeqCst );
+                            self.is_paused.store( false, Ordering::SeqCst );
+                        },
+                        Err( error ) => {
+                            log::error!( "Failed to turn the exclusive mode back on for '{}': {}", self.info.name, error );
                         }
                     }
-                },
-                linux_input::ForceFeedbackRequest::Other { .. } => {}
+
+                    return;
+                }
+
+                log::warn!( "Emergency ungrab triggered for '{}'; its events won't be processed until the combo is pressed again", self.info.name );
+                self.is_paused.store( true, Ordering::SeqCst );
+                if self.is_grabbed.swap( false, Ordering::SeqCst ) {
+                    if let Err( error ) = self.device.release() {
+                        log::error!( "Failed to turn off the exclusive mode for '{}': {}", self.info.name, error );
+                    }
+                }
+
+                if let Some( global_state ) = global_state {
+                    self.restore_permissions( &global_state );
+                    global_state.release_held_keys( Some( self.source_id ) );
+                }
             }
         }
     }
-}
 
-impl DeviceState {
     fn thread_main( self: Arc< DeviceState > ) {
         struct Cleanup {
             device_state: Arc< DeviceState >,
-            is_running: Arc< AtomicBool >,
-            condvar: Arc< Condvar >
+            pipe: Arc< EventPipe< linux_input::InputEvent > >
         }
 
-        let condvar = Arc::new( Condvar::new() );
-        let is_running = Arc::new( AtomicBool::new( true ) );
+        let pipe = Arc::new( EventPipe::new() );
         let cleanup = Cleanup {
             device_state: self.clone(),
-            is_running: is_running.clone(),
-            condvar: condvar.clone()
+            pipe: pipe.clone()
         };
 
         impl Drop for Cleanup {
             fn drop( &mut self ) {
-                self.is_running.store( false, Ordering::SeqCst );
-                self.condvar.notify_all();
+                self.pipe.close();
 
                 if let Some( global_state ) = self.device_state.global_state.upgrade() {
+                    global_state.release_held_keys( Some( self.device_state.source_id ) );
+
                     let mut devices_by_internal_name = global_state.devices_by_internal_name.write();
                     for internal_name in &self.device_state.matched_filters {
                         if let Some( list ) = devices_by_internal_name.get_mut( internal_name ) {
-                            list.retain( |other_device_state| !Arc::ptr_eq( &self.device_state, &other_device_state ) );
+                            list.retain( |other_device_state| !Arc::ptr_eq( &self.device_state, other_device_state ) );
                         }
                     }
+                    std::mem::drop( devices_by_internal_name );
+
+                    for virtual_device in global_state.virtual_device_by_internal_name.read().values() {
+                        virtual_device.on_force_feedback_device_disconnected( self.device_state.source_id );
+                    }
+                }
+
+                if let Some( global_state ) = self.device_state.global_state.upgrade() {
+                    self.device_state.restore_permissions( &global_state );
+                }
+
+                for emulation in &self.device_state.emulations {
+                    emulation.reset( self.device_state.source_id );
                 }
 
-                if let Some( ref old_permissions ) = self.device_state.old_permissions {
-                    let _ = std::fs::set_permissions( &self.device_state.path, old_permissions.clone() );
+                for button_scroll in &self.device_state.button_scrolls {
+                    button_scroll.reset( self.device_state.source_id );
+                }
+
+                for remap in &self.device_state.remaps {
+                    remap.reset( self.device_state.source_id );
                 }
             }
         }
 
-        let pipe = Arc::new( Mutex::new( Vec::new() ) );
         let thread_handle = {
             let pipe = pipe.clone();
-            let condvar = condvar.clone();
             let itself = self.clone();
             std::thread::spawn( move || {
+                CURRENT_SOURCE.with( |source| source.set( Some( itself.source_id ) ) );
                 let mut buffer: Vec< linux_input::InputEvent > = Vec::new();
-                let mut source = pipe.lock();
-                let mut pressed_alt = false;
-                let mut pressed_ctrl = false;
-                let mut pressed_escape = false;
-                let mut pressed_pause = false;
-
-                while RUNNING.load( Ordering::Relaxed ) {
-                    if source.is_empty() {
-                        condvar.wait( &mut source );
-                        continue;
-                    }
-
-                    std::mem::swap( &mut buffer, &mut source );
-                    std::mem::drop( source );
+                let mut remapped = Vec::new();
+                let mut translated = Vec::new();
+                let mut mapped = Vec::new();
+                let mut pressed_combo_keys = HashSet::new();
 
+                // Exits once the reader closes the pipe, so that the cleanup can run when the device is disconnected.
+                while RUNNING.load( Ordering::Relaxed ) && pipe.receive( &mut buffer ) {
                     for event in buffer.drain(..) {
-                        if itself.exclusive {
+                        let is_paused = itself.is_paused.load( Ordering::Relaxed );
+                        if (is_paused || itself.is_grabbed.load( Ordering::Relaxed )) && !itself.emergency_combo.is_empty() {
                             match event.body {
-                                linux_input::InputEventBody::KeyPress( linux_input::Key::LeftAlt ) => pressed_alt = true,
-                                linux_input::InputEventBody::KeyRelease( linux_input::Key::LeftAlt ) => pressed_alt = false,
-                                linux_input::InputEventBody::KeyPress( linux_input::Key::LeftCtrl ) => pressed_ctrl = true,
-                                linux_input::InputEventBody::KeyRelease( linux_input::Key::LeftCtrl ) => pressed_ctrl = false,
-                                linux_input::InputEventBody::KeyPress( linux_input::Key::Escape ) => pressed_escape = true,
-                                linux_input::InputEventBody::KeyRelease( linux_input::Key::Escape ) => pressed_escape = false,
-                                linux_input::InputEventBody::KeyPress( linux_input::Key::Pause ) => pressed_pause = true,
-                                linux_input::InputEventBody::KeyRelease( linux_input::Key::Pause ) => pressed_pause = false,
+                                linux_input::InputEventBody::KeyPress( key ) if itself.emergency_combo.contains( &key ) => { pressed_combo_keys.insert( key ); },
+                                linux_input::InputEventBody::KeyRelease( key ) => { pressed_combo_keys.remove( &key ); },
                                 _ => {}
                             }
 
-                            if pressed_alt && pressed_ctrl && pressed_escape && pressed_pause {
-                                log::warn!( "Emergency exit triggered!" );
-                                RUNNING.store( false, Ordering::SeqCst );
+                            if pressed_combo_keys.len() == itself.emergency_combo.len() {
+                                pressed_combo_keys.clear();
+                                itself.on_emergency_combo();
 
-                                std::process::exit( 1 );
+                                // The rest of the combo was never seen by anything, so neither is its last key.
+                                continue;
                             }
                         }
 
+                        if is_paused {
+                            continue;
+                        }
+
                         log::trace!( "<< {:?}: {:?}", itself.path, event );
-                        for script in &itself.scripts {
-                            let mut state = script.script_state.lock();
-                            state.event = event.clone();
-                            std::mem::drop( state );
+                        let timestamp = event.timestamp;
+                        let mut gesture = None;
+                        if let Some( ref multitouch ) = itself.multitouch {
+                            multitouch.feed( &event.body );
+                            if let (Some( ref recognizer ), linux_input::InputEventBody::Flush) = (&itself.gestures, &event.body) {
+                                gesture = recognizer.update( &multitouch.contacts(), event.timestamp.as_f64() * 1000.0 );
+                            }
+                        }
 
-                            script.eval();
+                        match itself.absolute_mapper {
+                            Some( ref mapper ) if mapper.translate( &event, &mut mapped ) => {
+                                for event in mapped.drain( .. ) {
+                                    itself.dispatch_event( event, &mut translated, &mut remapped );
+                                }
+                            },
+                            _ => itself.dispatch_event( event, &mut translated, &mut remapped )
                         }
-                    }
 
-                    source = pipe.lock();
+                        if let Some( gesture ) = gesture {
+                            itself.on_gesture( gesture, timestamp );
+                        }
+                    }
                 }
             })
         };
@@ -480,21 +1341,20 @@ impl DeviceState {
                 }
             };
 
-            match event.body {
-                linux_input::InputEventBody::Dropped => {
-                    log::warn!( "Buffer overflow on device '{}' ({:?})", self.info.name, self.path );
-                    // TODO: Actually handle there: https://www.freedesktop.org/software/libevdev/doc/latest/syn_dropped.html
-                },
-                _ => {}
+            // Those are only the echoes of the LEDs which we've set ourselves.
+            if let linux_input::InputEventBody::Other { kind: EventKind::LED, .. } = event.body {
+                continue;
             }
 
-            pipe.lock().push( event );
-            condvar.notify_all();
-        }
+            if event.body == linux_input::InputEventBody::Dropped {
+                log::warn!( "Buffer overflow on device '{}' ({:?})", self.info.name, self.path );
+                // TODO: Actually handle there: https://www.freedesktop.org/software/libevdev/doc/latest/syn_dropped.html
+            }
 
-        is_running.store( false, Ordering::SeqCst );
-        condvar.notify_all();
+            pipe.push( event );
+        }
 
+        pipe.close();
         let _ = thread_handle.join();
         std::mem::drop( cleanup );
     }
@@ -511,8 +1371,25 @@ fn populate_with_constants( scope: &mut rhai::Scope ) {
         scope.push_constant( format!( "Rel_{}", name ), value.raw() as i32 | (EventKind::RelativeAxis.raw() as i32) << 16 );
     }
 
+    for &(name, value) in crate::event::EXTRA_KEY_LIST {
+        scope.push_constant( format!( "Key_{}", name ), pack_code( EventKind::Key, value ) );
+    }
+    for &(name, value) in crate::event::EXTRA_ABS_LIST {
+        scope.push_constant( format!( "Abs_{}", name ), pack_code( EventKind::AbsoluteAxis, value ) );
+    }
+    for &(name, value) in crate::event::EXTRA_REL_LIST {
+        scope.push_constant( format!( "Rel_{}", name ), pack_code( EventKind::RelativeAxis, value ) );
+    }
+
+    for &(name, value) in crate::event::LED_LIST {
+        scope.push_constant( format!( "Led_{}", name ), pack_code( EventKind::LED, value ) );
+    }
+    for &(name, value) in crate::event::SWITCH_LIST {
+        scope.push_constant( format!( "Sw_{}", name ), pack_code( EventKind::Switch, value ) );
+    }
+
     for (name, value) in linux_input::EventKind::LIST {
-        scope.push_constant( format!( "{}", name ), value.raw() as i32 );
+        scope.push_constant( name.to_string(), value.raw() as i32 );
     }
 
     scope.push_constant( "Rel", linux_input::EventKind::RelativeAxis.raw() as i32 );
@@ -542,33 +1419,109 @@ fn create_rhai_engine() -> rhai::Engine {
     engine.register_fn( "pi", move || std::f64::consts::PI );
     engine.register_fn( "to_float", move |value: i32| value as f64 );
     engine.register_fn( "to_int", move |value: f64| value as i32 );
+    crate::axis::register_functions( &mut engine );
 
     engine
 }
 
 struct Script {
+    label: String,
     device: String,
+    error_limit: u32,
+    error_window: std::time::Duration,
+    on_error_limit: ErrorPolicy,
+    passthrough_to: Option< String >,
+    default_target: Option< String >,
+    passthrough: bool,
+    limits: ScriptLimits,
     ast: rhai::AST
 }
 
+impl Script {
+    fn compile( engine: &rhai::Engine, config_path: &Path, label: String, script: &crate::config::Script ) -> Option< Self > {
+        let ast = match engine.compile( &script.code ) {
+            Ok( ast ) => ast,
+            Err( error ) => {
+                let position = error.position();
+                log::error!(
+                    "Failed to compile {} from {:?} at line {}, column {}: {}",
+                    label,
+                    config_path,
+                    position.line().unwrap_or( 0 ),
+                    position.position().unwrap_or( 0 ),
+                    error.err_type()
+                );
+                return None;
+            }
+        };
+
+        let mut scope = rhai::Scope::new();
+        populate_with_constants( &mut scope );
+        let ast = engine.optimize_ast( &scope, ast, rhai::OptimizationLevel::Simple );
+        Some( Script {
+            label,
+            device: script.device.clone(),
+            error_limit: script.error_limit,
+            error_window: script.error_window,
+            on_error_limit: script.on_error_limit.clone(),
+            passthrough_to: script.passthrough_to.clone(),
+            default_target: script.default_target.clone(),
+            passthrough: script.passthrough,
+            limits: script.limits.clone(),
+            ast
+        })
+    }
+}
+
+/// Checks whether the script was aborted due to exceeding one of its execution limits.
+fn is_limit_violation( error: &rhai::EvalAltResult ) -> bool {
+    match *error {
+        rhai::EvalAltResult::ErrorInFunctionCall( _, _, ref error, _ ) => is_limit_violation( error ),
+        rhai::EvalAltResult::ErrorTooManyOperations( .. ) |
+        rhai::EvalAltResult::ErrorStackOverflow( .. ) |
+        rhai::EvalAltResult::ErrorDataTooLarge( .. ) |
+        rhai::EvalAltResult::ErrorTerminated( .. ) => true,
+        _ => false
+    }
+}
+
 struct ScriptInstance {
     engine: rhai::Engine,
     script: Arc< Script >,
     scope: Mutex< rhai::Scope< 'static > >,
-    script_state: Arc< Mutex< ScriptState > >
+    script_state: Arc< Mutex< ScriptState > >,
+    recent_errors: Mutex< VecDeque< Instant > >,
+    is_disabled: AtomicBool,
+    device_ref: rhai::ImmutableString,
+    device_name: rhai::ImmutableString
 }
 
 struct ScriptState {
     global_state: Weak< GlobalState >,
     event: linux_input::InputEvent,
-    initialized: bool
+    initialized: bool,
+    deadline: Option< Instant >,
+    /// Whether the current event was already sent, consumed or replaced by the script.
+    handled: bool,
+    /// Whether the current event, or its replacement, was already sent out by the script.
+    forwarded: bool,
+    /// The effects uploaded through `rumble`, one per target.
+    rumble_effects: HashMap< String, (Weak< DeviceState >, i16) >,
+    /// The fractional parts of the motion sent through `send_rel` with a float delta.
+    rel_remainders: HashMap< (String, linux_input::RelativeAxis), f64 >,
+    /// The wheels used by `send_wheel` and `send_hwheel`, one per target and direction.
+    wheels: HashMap< (String, bool), Wheel >,
+    /// The contacts sent through `send_contacts`, one per target.
+    touch_emitters: HashMap< String, TouchEmitter >,
+    /// The gesture which the script is currently being evaluated for.
+    gesture: Option< Gesture >
 }
 
 impl ScriptState {
     fn send_event< T >( &self, target: &str, body: T ) where T: AsRef< linux_input::InputEventBody > {
         log::trace!( "send_event: target={:?} body={:?}", target, body.as_ref() );
         let global_state = self.global_state.upgrade().unwrap();
-        if let Some( device ) = global_state.lookup_device_by_internal_name( &target ) {
+        if let Some( device ) = global_state.lookup_device_by_internal_name( target ) {
             if let Err( error ) = device.emit( body ) {
                 log::error!( "Failed to send an event to '{}': {}", target, error );
             }
@@ -576,6 +1529,99 @@ impl ScriptState {
             log::error!( "[script] send_to: no such device: '{}'", target );
         }
     }
+
+    /// Forwards the current event, unless the script runs for a gesture, which isn't an input event.
+    fn forward_event( &mut self, target: &str ) {
+        if self.gesture.is_none() {
+            self.send_event( target, self.event.clone() );
+            self.forwarded = true;
+        }
+    }
+
+    fn replace_event( &mut self, target: Option< &str >, code: i32, value: i32 ) {
+        self.handled = true;
+        let target = match target {
+            Some( target ) => target,
+            None => {
+                log::error!( "[script] replace: no 'default-target' specified" );
+                return;
+            }
+        };
+
+        let event = linux_input::RawInputEvent {
+            timestamp: self.event.timestamp,
+            kind: (code >> 16) as u16,
+            code: (code & 0xFFFF) as u16,
+            value
+        };
+
+        self.send_event( target, linux_input::InputEvent::from( event ) );
+        self.forwarded = true;
+    }
+
+    /// Scrolls the target's wheel, sending both the high-resolution and the legacy events.
+    fn scroll( &mut self, target: &str, horizontal: bool, callback: impl FnOnce( &mut Wheel, linux_input::RelativeAxis, linux_input::RelativeAxis, &mut Vec< linux_input::InputEventBody > ) ) {
+        let (axis, hi_res_axis) = if horizontal {
+            (linux_input::RelativeAxis::Other( crate::event::REL_HWHEEL ), linux_input::RelativeAxis::Other( crate::event::REL_HWHEEL_HI_RES ))
+        } else {
+            (linux_input::RelativeAxis::Wheel, linux_input::RelativeAxis::WheelHiRes)
+        };
+
+        let mut output = Vec::new();
+        callback( self.wheels.entry( (target.to_owned(), horizontal) ).or_default(), axis, hi_res_axis, &mut output );
+        for body in output {
+            self.send_event( target, body );
+        }
+    }
+
+    fn rumble( &mut self, target: &str, mut effect: Effect ) {
+        let global_state = self.global_state.upgrade().unwrap();
+        let device_state = match find_device_for_ff( &global_state, target ) {
+            Some( device_state ) => device_state,
+            None => {
+                log::error!( "[script] rumble: no device with force feedback: '{}'", target );
+                return;
+            }
+        };
+
+        // Reuse the previously uploaded effect; the number of effect slots is very limited.
+        effect.id = match self.rumble_effects.get( target ) {
+            Some( (previous, effect_id) ) if previous.as_ptr() == Arc::as_ptr( &device_state ) => *effect_id,
+            _ => -1
+        };
+
+        let effect_id = match device_state.upload_force_feedback_effect( &effect ) {
+            Ok( effect_id ) => effect_id,
+            Err( error ) => {
+                log::warn!( "Failed to upload a force feedback effect on device '{}': {}", device_state.info.name, error );
+                return;
+            }
+        };
+
+        self.rumble_effects.insert( target.to_owned(), (Arc::downgrade( &device_state ), effect_id) );
+        if let Err( error ) = device_state.play_force_feedback_effect( effect_id, 1 ) {
+            log::warn!( "Failed to play a force feedback effect on device '{}': {}", device_state.info.name, error );
+        }
+    }
+
+    fn stop_rumble( &mut self, target: &str ) {
+        let (device_state, effect_id) = match self.rumble_effects.get( target ) {
+            Some( (device_state, effect_id) ) => (device_state.upgrade(), *effect_id),
+            None => return
+        };
+
+        let device_state = match device_state {
+            Some( device_state ) => device_state,
+            None => {
+                self.rumble_effects.remove( target );
+                return;
+            }
+        };
+
+        if let Err( error ) = device_state.stop_force_feedback_effect( effect_id ) {
+            log::warn!( "Failed to stop a force feedback effect on device '{}': {}", device_state.info.name, error );
+        }
+    }
 }
 
 fn debug_print_event( event: linux_input::RawInputEvent ) -> String {
@@ -592,6 +1638,9 @@ fn debug_print_event( event: linux_input::RawInputEvent ) -> String {
                 key => write!( &mut output, "key: Key_{}, ", key ).unwrap()
             }
             write!( &mut output, "press: {}", event.value == 1 ).unwrap();
+            if event.value == 2 {
+                write!( &mut output, ", repeat: true" ).unwrap();
+            }
         },
         EventKind::AbsoluteAxis => {
             write!( &mut output, "type: Abs, " ).unwrap();
@@ -609,6 +1658,24 @@ fn debug_print_event( event: linux_input::RawInputEvent ) -> String {
             }
             write!( &mut output, "delta: {}", event.value ).unwrap();
         },
+        EventKind::LED | EventKind::Switch => {
+            let (prefix, list) = if kind == EventKind::LED {
+                ("Led", crate::event::LED_LIST)
+            } else {
+                ("Sw", crate::event::SWITCH_LIST)
+            };
+
+            write!( &mut output, "type: {}, ", kind ).unwrap();
+            match list.iter().find( |&&(_, code)| code == event.code ) {
+                Some( (name, _) ) => write!( &mut output, "{}: {}_{}, ", prefix.to_lowercase(), prefix, name ).unwrap(),
+                None => write!( &mut output, "raw_code: {}, ", event.code ).unwrap()
+            }
+            write!( &mut output, "on: {}", event.value != 0 ).unwrap();
+        },
+        EventKind::Misc if event.code == crate::event::MSC_SCAN => {
+            write!( &mut output, "type: {}, ", kind ).unwrap();
+            write!( &mut output, "scancode: 0x{:X}", event.value ).unwrap();
+        },
         _ => {
             write!( &mut output, "type: {}, ", kind ).unwrap();
             write!( &mut output, "raw_code: {}, ", event.code ).unwrap();
@@ -619,8 +1686,29 @@ fn debug_print_event( event: linux_input::RawInputEvent ) -> String {
     output
 }
 
+/// Registers a setter on the script's event object.
+///
+/// Any modification of the current event is mirrored into the script's state, so that
+/// a subsequent `send_to` (or the automatic passthrough) emits the modified event.
+fn register_event_setter< T: Clone + Send + Sync + 'static >(
+    engine: &mut rhai::Engine,
+    script_state: &Arc< Mutex< ScriptState > >,
+    name: &str,
+    setter: fn( &mut linux_input::RawInputEvent, T ) -> Result< (), Box< rhai::EvalAltResult > >
+) {
+    let script_state = script_state.clone();
+    engine.register_set( name, move |this: &mut ScriptEvent, value: T| -> Result< (), Box< rhai::EvalAltResult > > {
+        setter( &mut this.raw, value )?;
+        if this.is_current {
+            script_state.lock().event = this.raw.clone().into();
+        }
+
+        Ok(())
+    });
+}
+
 impl ScriptInstance {
-    fn new( global_state: &Arc< GlobalState >, script: Arc< Script > ) -> Self {
+    fn new( global_state: &Arc< GlobalState >, script: Arc< Script >, device_name: &str, source_axes: &[AbsoluteAxisBit], multitouch: Option< Arc< Multitouch > > ) -> Self {
 
         let script_state = ScriptState {
             global_state: Arc::downgrade( global_state ),
@@ -628,58 +1716,133 @@ impl ScriptInstance {
                 timestamp: linux_input::Timestamp::default(),
                 body: linux_input::InputEventBody::Flush
             },
-            initialized: false
+            initialized: false,
+            deadline: None,
+            handled: false,
+            forwarded: false,
+            rumble_effects: HashMap::new(),
+            rel_remainders: HashMap::new(),
+            wheels: HashMap::new(),
+            touch_emitters: HashMap::new(),
+            gesture: None
         };
 
         let script_state = Arc::new( Mutex::new( script_state ) );
         let mut engine = create_rhai_engine();
         engine.on_print( |str| log::info!( "[script] {}", str ) );
-        engine.register_type::< linux_input::RawInputEvent >();
-        engine.register_get( "timestamp", (|this| this.timestamp.as_f64()) as fn( &mut linux_input::RawInputEvent ) -> f64 );
-        engine.register_get( "type", (|this| this.kind as i32) as fn( &mut linux_input::RawInputEvent ) -> i32 );
-        engine.register_get( "raw_code", (|this| this.code as i32) as fn( &mut linux_input::RawInputEvent ) -> i32 );
-        engine.register_get( "raw_value", (|this| this.value as i32) as fn( &mut linux_input::RawInputEvent ) -> i32 );
-        engine.register_get( "key", (|this|
-            if this.kind == linux_input::EventKind::Key.raw() {
-                ((this.code as i32) | ((this.kind as i32) << 16)).into()
-            } else {
-                ().into()
-            }) as fn( &mut linux_input::RawInputEvent ) -> rhai::Dynamic );
-        engine.register_get( "axis", (|this|
-            if this.kind == linux_input::EventKind::AbsoluteAxis.raw() || this.kind == linux_input::EventKind::RelativeAxis.raw() {
-                ((this.code as i32) | ((this.kind as i32) << 16)).into()
-            } else {
-                ().into()
-            }) as fn( &mut linux_input::RawInputEvent ) -> rhai::Dynamic );
-        engine.register_get( "position", (|this|
-            if this.kind == linux_input::EventKind::AbsoluteAxis.raw() {
-                (this.value as i32).into()
-            } else {
-                ().into()
-            }) as fn( &mut linux_input::RawInputEvent ) -> rhai::Dynamic );
-        engine.register_get( "delta", (|this|
-            if this.kind == linux_input::EventKind::AbsoluteAxis.raw() {
-                (this.value as i32).into()
-            } else {
-                ().into()
-            }) as fn( &mut linux_input::RawInputEvent ) -> rhai::Dynamic );
-        engine.register_get( "press", (|this| this.kind == linux_input::EventKind::Key.raw() && this.value != 0) as fn( &mut linux_input::RawInputEvent ) -> bool );
-        engine.register_get( "release", (|this| this.kind == linux_input::EventKind::Key.raw() && this.value == 0) as fn( &mut linux_input::RawInputEvent ) -> bool );
+
+        let limits = &script.limits;
+        engine.set_max_operations( limits.max_operations );
+        engine.set_max_call_levels( limits.max_call_depth );
+        engine.set_max_string_size( limits.max_string_size );
+        engine.set_max_array_size( limits.max_array_size );
+        engine.set_max_map_size( limits.max_map_size );
+        {
+            let script_state = script_state.clone();
+            engine.on_progress( move |operations| {
+                // Checking the clock is relatively expensive, so don't do it on every operation.
+                if operations % 256 != 0 {
+                    return None;
+                }
+
+                match script_state.lock().deadline {
+                    Some( deadline ) if Instant::now() >= deadline => Some( "time limit exceeded".into() ),
+                    _ => None
+                }
+            });
+        }
+        crate::event::register_getters( &mut engine );
+
+        register_event_setter( &mut engine, &script_state, "key", |this, key: i32| {
+            if (key >> 16) != linux_input::EventKind::Key.raw() as i32 {
+                return Err( "key: not a key".into() );
+            }
+
+            this.kind = linux_input::EventKind::Key.raw();
+            this.code = (key & 0xFFFF) as u16;
+            Ok(())
+        });
+        register_event_setter( &mut engine, &script_state, "axis", |this, axis: i32| {
+            this.kind = (axis >> 16) as u16;
+            this.code = (axis & 0xFFFF) as u16;
+            Ok(())
+        });
+        register_event_setter( &mut engine, &script_state, "value", |this, value: i32| { this.value = value; Ok(()) } );
+        register_event_setter( &mut engine, &script_state, "position", |this, position: i32| { this.value = position; Ok(()) } );
+        register_event_setter( &mut engine, &script_state, "delta", |this, delta: i32| { this.value = delta; Ok(()) } );
+        register_event_setter( &mut engine, &script_state, "press", |this, press: bool| {
+            if this.kind != linux_input::EventKind::Key.raw() {
+                return Err( "press: not a key event".into() );
+            }
+
+            this.value = press as i32;
+            Ok(())
+        });
 
         engine.register_fn( "raw_key", |key: i32| key | ((linux_input::EventKind::Key.raw() as i32) << 16) );
         engine.register_fn( "raw_rel", |axis: i32| axis | ((linux_input::EventKind::RelativeAxis.raw() as i32) << 16) );
         engine.register_fn( "raw_abs", |axis: i32| axis | ((linux_input::EventKind::AbsoluteAxis.raw() as i32) << 16) );
 
-        engine.register_fn( "print", move |event: linux_input::RawInputEvent| {
-            debug_print_event( event )
+        engine.register_fn( "print", move |event: ScriptEvent| {
+            debug_print_event( event.raw )
         });
 
         {
             let script_state = script_state.clone();
             engine.register_fn( "send_to", move |target: String| {
                 log::trace!( "script: send_to: target={:?}", target );
-                let script_state = script_state.lock();
-                script_state.send_event( &target, script_state.event.clone() );
+                let mut script_state = script_state.lock();
+                script_state.forward_event( &target );
+                script_state.handled = true;
+            });
+        }
+        {
+            let script_state = script_state.clone();
+            // Sends an extra event, e.g. a modified copy; unlike the other variants this doesn't count as handling the current event.
+            engine.register_fn( "send_to", move |target: String, event: ScriptEvent| {
+                log::trace!( "script: send_to: target={:?} event={:?}", target, event.raw );
+                if event.gesture.is_some() {
+                    return;
+                }
+
+                script_state.lock().send_event( &target, linux_input::InputEvent::from( event.raw ) );
+            });
+        }
+        {
+            let script_state = script_state.clone();
+            let default_target = script.default_target.clone();
+            engine.register_fn( "send_to", move || {
+                log::trace!( "script: send_to: target={:?}", default_target );
+                let mut script_state = script_state.lock();
+                if let Some( ref target ) = default_target {
+                    script_state.forward_event( target );
+                } else {
+                    log::error!( "[script] send_to: no 'default-target' specified" );
+                }
+                script_state.handled = true;
+            });
+        }
+        {
+            let script_state = script_state.clone();
+            engine.register_fn( "consume", move || {
+                script_state.lock().handled = true;
+            });
+        }
+        {
+            let script_state = script_state.clone();
+            let default_target = script.default_target.clone();
+            engine.register_fn( "replace", move |code: i32| {
+                let mut script_state = script_state.lock();
+                let value = linux_input::RawInputEvent::from( script_state.event.clone() ).value;
+                script_state.replace_event( default_target.as_deref(), code, value );
+            });
+        }
+        {
+            let script_state = script_state.clone();
+            let default_target = script.default_target.clone();
+            engine.register_fn( "replace", move |code: i32, value: i32| {
+                let mut script_state = script_state.lock();
+                script_state.replace_event( default_target.as_deref(), code, value );
             });
         }
         {
@@ -808,6 +1971,47 @@ impl ScriptInstance {
                 script_state.send_event( &target, linux_input::InputEventBody::RelativeMove { axis, delta } );
             });
         }
+        {
+            let script_state = script_state.clone();
+            engine.register_fn( "send_rel", move |target: String, axis: i32, delta: f64| {
+                let mut script_state = script_state.lock();
+                let axis = linux_input::RelativeAxis::from( (axis & 0xFFFF) as u16 );
+                let remainder = script_state.rel_remainders.entry( (target.clone(), axis) ).or_insert( 0.0 );
+                let delta = *remainder + delta;
+                *remainder = delta.fract();
+
+                let delta = delta.trunc() as i32;
+                if delta != 0 {
+                    script_state.send_event( &target, linux_input::InputEventBody::RelativeMove { axis, delta } );
+                }
+            });
+        }
+        for &(name, horizontal) in &[("send_wheel", false), ("send_hwheel", true)] {
+            {
+                let script_state = script_state.clone();
+                engine.register_fn( name, move |target: String, notches: i32| {
+                    script_state.lock().scroll( &target, horizontal, |wheel, axis, hi_res_axis, output| {
+                        wheel.scroll_hi_res( notches.saturating_mul( crate::scroll::HI_RES_PER_NOTCH ), axis, hi_res_axis, output );
+                    });
+                });
+            }
+            {
+                let script_state = script_state.clone();
+                engine.register_fn( name, move |target: String, notches: f64| {
+                    script_state.lock().scroll( &target, horizontal, |wheel, axis, hi_res_axis, output| {
+                        wheel.scroll( notches, axis, hi_res_axis, output );
+                    });
+                });
+            }
+        }
+        for &(name, horizontal) in &[("send_wheel_hi_res", false), ("send_hwheel_hi_res", true)] {
+            let script_state = script_state.clone();
+            engine.register_fn( name, move |target: String, value: i32| {
+                script_state.lock().scroll( &target, horizontal, |wheel, axis, hi_res_axis, output| {
+                    wheel.scroll_hi_res( value, axis, hi_res_axis, output );
+                });
+            });
+        }
         {
             let script_state = script_state.clone();
             engine.register_fn( "send_flush", move |target: String| {
@@ -816,6 +2020,103 @@ impl ScriptInstance {
             });
         }
 
+        {
+            let script_state = script_state.clone();
+            engine.register_fn( "rumble", move |target: String, strong: i32, weak: i32, length: i32| {
+                let effect = Effect::rumble(
+                    strong.clamp( 0, 0xFFFF ) as u16,
+                    weak.clamp( 0, 0xFFFF ) as u16,
+                    std::time::Duration::from_millis( length.max( 0 ) as u64 )
+                );
+
+                script_state.lock().rumble( &target, effect );
+            });
+        }
+        {
+            let script_state = script_state.clone();
+            engine.register_fn( "stop_rumble", move |target: String| {
+                script_state.lock().stop_rumble( &target );
+            });
+        }
+
+        {
+            let ranges: HashMap< u16, (i32, i32) > = source_axes.iter().map( |bit| (bit.axis.raw(), (bit.minimum, bit.maximum)) ).collect();
+            engine.register_fn( "axis_range", move |axis: i32| -> Result< rhai::Array, Box< rhai::EvalAltResult > > {
+                let (min, max) = ranges.get( &((axis & 0xFFFF) as u16) ).cloned().ok_or( "axis_range: the device has no such axis" )?;
+                Ok( vec![ min.into(), max.into() ] )
+            });
+        }
+        {
+            let global_state = Arc::downgrade( global_state );
+            engine.register_fn( "axis_range", move |target: String, axis: i32| -> Result< rhai::Array, Box< rhai::EvalAltResult > > {
+                let global_state = global_state.upgrade().ok_or( "axis_range: shutting down" )?;
+                let virtual_device = global_state.config.virtual_devices.get( &target ).ok_or( "axis_range: no such virtual device" )?;
+                let bit = virtual_device.abs_bits.iter().find( |bit| bit.axis.raw() == (axis & 0xFFFF) as u16 ).ok_or( "axis_range: the device has no such axis" )?;
+                Ok( vec![ bit.minimum.into(), bit.maximum.into() ] )
+            });
+        }
+
+        engine.register_fn( "contacts", move || -> Result< rhai::Array, Box< rhai::EvalAltResult > > {
+            let multitouch = multitouch.as_ref().ok_or( "contacts: the device has no multitouch slots" )?;
+            Ok( multitouch.contacts().iter().map( |contact| contact.to_script_map().into() ).collect() )
+        });
+        {
+            let script_state = script_state.clone();
+            engine.register_fn( "send_contacts", move |target: String, contacts: rhai::Array| -> Result< (), Box< rhai::EvalAltResult > > {
+                let mut parsed = Vec::with_capacity( contacts.len() );
+                for contact in contacts {
+                    let contact = contact.try_cast::< rhai::Map >()
+                        .and_then( |contact| Contact::from_script_map( &contact ) )
+                        .ok_or( "send_contacts: every contact has to be a map with a valid 'slot'" )?;
+                    parsed.push( contact );
+                }
+
+                let mut script_state = script_state.lock();
+                let mut output = Vec::new();
+                script_state.touch_emitters.entry( target.clone() ).or_default().emit( &parsed, &mut output );
+                for body in output {
+                    script_state.send_event( &target, body );
+                }
+                Ok(())
+            });
+        }
+        {
+            let global_state = Arc::downgrade( global_state );
+            let device = script.device.clone();
+            engine.register_fn( "pointer_profile", move || -> Result< String, Box< rhai::EvalAltResult > > {
+                let global_state = global_state.upgrade().ok_or( "pointer_profile: shutting down" )?;
+                let pointer = global_state.pointers.iter().find( |pointer| pointer.device == device ).ok_or( "pointer_profile: the device has no [[pointer]] entry" )?;
+                Ok( pointer.profile_name() )
+            });
+        }
+        {
+            let global_state = Arc::downgrade( global_state );
+            let device = script.device.clone();
+            engine.register_fn( "set_pointer_profile", move |profile: String| -> Result< (), Box< rhai::EvalAltResult > > {
+                let global_state = global_state.upgrade().ok_or( "set_pointer_profile: shutting down" )?;
+                global_state.set_pointer_profile( &device, &profile ).map_err( |error| error.into() )
+            });
+        }
+        {
+            let global_state = Arc::downgrade( global_state );
+            engine.register_fn( "set_pointer_profile", move |device: String, profile: String| -> Result< (), Box< rhai::EvalAltResult > > {
+                let global_state = global_state.upgrade().ok_or( "set_pointer_profile: shutting down" )?;
+                global_state.set_pointer_profile( &device, &profile ).map_err( |error| error.into() )
+            });
+        }
+
+        {
+            let global_state = Arc::downgrade( global_state );
+            engine.register_fn( "set_led", move |device: String, led: i32, is_on: bool| -> Result< (), Box< rhai::EvalAltResult > > {
+                if (led >> 16) as u16 != EventKind::LED.raw() {
+                    return Err( "set_led: not a LED".into() );
+                }
+
+                let global_state = global_state.upgrade().ok_or( "set_led: shutting down" )?;
+                global_state.set_led( &device, (led & 0xFFFF) as u16, is_on ).map_err( |error| error.into() )
+            });
+        }
+
         engine.register_fn( "run", move |command: String| {
             let _ = std::process::Command::new( "sh" )
                 .arg( "-c" )
@@ -830,118 +2131,256 @@ impl ScriptInstance {
         ScriptInstance {
             engine,
             script_state,
-            script,
-            scope: Mutex::new( scope )
+            scope: Mutex::new( scope ),
+            recent_errors: Mutex::new( VecDeque::new() ),
+            is_disabled: AtomicBool::new( false ),
+            device_ref: script.device.as_str().into(),
+            device_name: device_name.into(),
+            script
+        }
+    }
+
+    fn forward_if_unhandled( &self, target: Option< &str > ) {
+        let script_state = self.script_state.lock();
+        if script_state.handled || script_state.gesture.is_some() {
+            return;
         }
+
+        if let Some( target ) = target {
+            script_state.send_event( target, script_state.event.clone() );
+        }
+    }
+
+    /// Passes the current event through after the script failed on it, unless it was already sent out.
+    ///
+    /// Whether the script consumed the event doesn't matter, since it didn't get to finish.
+    fn pass_through_on_error( &self, name: &str ) {
+        let mut script_state = self.script_state.lock();
+        if script_state.forwarded || script_state.gesture.is_some() {
+            return;
+        }
+
+        match self.script.passthrough_to {
+            Some( ref target ) => script_state.forward_event( target ),
+            None if name == "event" => log::error!( "The {} has no 'passthrough-to' or 'default-target'; the event was dropped", self.script.label ),
+            None => {}
+        }
+    }
+
+    fn is_disabled( &self ) -> bool {
+        self.is_disabled.load( Ordering::Relaxed )
+    }
+
+    fn was_handled( &self ) -> bool {
+        self.script_state.lock().handled
+    }
+
+    /// Evaluates the script for the current event.
+    ///
+    /// Returns `false` if the script has just exceeded its error budget and got disabled.
+    fn eval( &self ) -> bool {
+        let script_state = self.script_state.lock();
+        let event = ScriptEvent {
+            raw: script_state.event.clone().into(),
+            device_ref: self.device_ref.clone(),
+            device_name: self.device_name.clone(),
+            gesture: script_state.gesture,
+            is_current: true
+        };
+        std::mem::drop( script_state );
+
+        self.run( "event", rhai::Dynamic::from( event ) )
+    }
+
+    /// Evaluates the script for a recognized gesture, which is seen as a synchronization event of kind `Gesture`.
+    ///
+    /// That event can't be forwarded, so that catch-all scripts don't send a stray synchronization event.
+    fn eval_gesture( &self, gesture: Gesture, timestamp: linux_input::Timestamp ) -> bool {
+        let mut script_state = self.script_state.lock();
+        script_state.event = linux_input::InputEvent { timestamp, body: linux_input::InputEventBody::Flush };
+        script_state.gesture = Some( gesture );
+        std::mem::drop( script_state );
+
+        let result = self.eval();
+        self.script_state.lock().gesture = None;
+        result
+    }
+
+    /// Evaluates an `on-force-feedback` script for a force feedback request.
+    fn eval_force_feedback( &self, request: rhai::Map ) -> bool {
+        self.run( "request", request.into() )
     }
 
-    fn eval( &self ) {
+    fn run( &self, name: &'static str, value: rhai::Dynamic ) -> bool {
         let mut scope = self.scope.lock();
         let scope_length = scope.len();
 
         {
             let mut script_state = self.script_state.lock();
-            let raw_event: linux_input::RawInputEvent = script_state.event.clone().into();
             scope.push( "first_run", !script_state.initialized );
-            scope.push( "event", raw_event );
+            scope.push_dynamic( name, value );
             script_state.initialized = true;
+            script_state.deadline = Some( Instant::now() + self.script.limits.time_limit );
+            script_state.handled = false;
+            script_state.forwarded = false;
         }
 
         // scope.push( "state", user_state );
         let result = self.engine.eval_ast_with_scope::< rhai::Dynamic >( &mut scope, &self.script.ast );
         scope.rewind( scope_length );
 
-        if let Err( error ) = result {
-            log::error!( "Error while evaluating script: {}", error );
+        let error = match result {
+            Ok( _ ) => {
+                if self.script.passthrough {
+                    self.forward_if_unhandled( self.script.default_target.as_deref() );
+                }
+                return true;
+            },
+            Err( error ) => error
+        };
+
+        log::error!( "Error while evaluating {}: {}", self.script.label, error );
+
+        if self.script.passthrough || is_limit_violation( &error ) {
+            self.pass_through_on_error( name );
         }
+
+        let now = Instant::now();
+        let mut recent_errors = self.recent_errors.lock();
+        while let Some( &timestamp ) = recent_errors.front() {
+            if now.duration_since( timestamp ) > self.script.error_window {
+                recent_errors.pop_front();
+            } else {
+                break;
+            }
+        }
+        recent_errors.push_back( now );
+
+        if recent_errors.len() < self.script.error_limit as usize {
+            return true;
+        }
+
+        log::error!(
+            "The {} failed {} times within {}; disabling it",
+            self.script.label,
+            recent_errors.len(),
+            humantime::format_duration( self.script.error_window )
+        );
+
+        self.is_disabled.store( true, Ordering::SeqCst );
+        false
     }
 }
 
 impl GlobalState {
     fn new( config: Config ) -> Option< Arc< Self > > {
         let mut scripts = Vec::new();
+        let mut force_feedback_hooks = HashMap::new();
+        let mut compilation_failed = false;
         let engine = create_rhai_engine();
-        for script in &config.scripts {
-            let ast = engine.compile( &script.code ).unwrap();
-            let mut scope = rhai::Scope::new();
-            populate_with_constants( &mut scope );
-            let ast = engine.optimize_ast( &scope, ast, rhai::OptimizationLevel::Simple );
-            scripts.push( Arc::new( Script {
-                device: script.device.clone(),
-                ast
-            }));
+        for (nth, script) in config.scripts.iter().enumerate() {
+            let label = format!( "script #{} (for '{}')", nth, script.device );
+            match Script::compile( &engine, &config.path, label, script ) {
+                Some( script ) => scripts.push( Arc::new( script ) ),
+                None => compilation_failed = true
+            }
+        }
+        for (virtual_device_name, virtual_device_config) in &config.virtual_devices {
+            if let Some( ref script ) = virtual_device_config.on_force_feedback {
+                let label = format!( "'on-force-feedback' script (for '{}')", virtual_device_name );
+                match Script::compile( &engine, &config.path, label, script ) {
+                    Some( script ) => { force_feedback_hooks.insert( virtual_device_name.clone(), Arc::new( script ) ); },
+                    None => compilation_failed = true
+                }
+            }
         }
         std::mem::drop( engine );
 
+        if compilation_failed {
+            return None;
+        }
+
+        let remaps = config.remaps.iter().map( |remap| Arc::new( RemapTable::new( remap ) ) ).collect();
+        let emulations = config.emulations.iter().map( |emulation| {
+            let target_axes = &config.virtual_devices[ &emulation.target ].abs_bits;
+            Arc::new( Emulation::new( emulation, target_axes ) )
+        }).collect();
+
+        let pointers = config.pointers.iter().map( |pointer| {
+            Arc::new( Pointer::new( pointer, &config.pointer_profiles[ &pointer.profile ] ) )
+        }).collect();
+
+        let button_scrolls = config.button_scrolls.iter().map( |button_scroll| Arc::new( ButtonScroll::new( button_scroll ) ) ).collect();
+
         let global_state = GlobalState {
             config,
             scripts,
+            remaps,
+            emulations,
+            pointers,
+            button_scrolls,
             devices_by_internal_name: Default::default(),
             virtual_device_by_rdev: Default::default(),
             virtual_device_by_internal_name: Default::default(),
+            journal: RestoreJournal::new( RESTORE_JOURNAL_PATH ),
+            grab_retries: Mutex::new( HashMap::new() )
         };
 
+        // Do this before we touch any of the devices ourselves.
+        global_state.journal.recover();
+
         let global_state = Arc::new( global_state );
 
-        let mut virtual_device_by_rdev = HashMap::new();
-        let mut virtual_device_by_internal_name = HashMap::new();
+        // This has to be set up before the devices are created so that we don't miss their events.
+        let mut monitor = match udev::MonitorBuilder::new().and_then( |builder| builder.match_subsystem( "input" ) ).and_then( |builder| builder.listen() ) {
+            Ok( monitor ) => monitor,
+            Err( error ) => {
+                log::error!( "Failed to start monitoring udev events: {}", error );
+                return None;
+            }
+        };
+
+        let mut created_devices = Vec::new();
         for (virtual_device_name, virtual_device_config) in &global_state.config.virtual_devices {
             // Not that it really matters, but the defaults come from here:
             // https://github.com/obdev/v-usb/blob/master/usbdrv/USB-IDs-for-free.txt
             let id = DeviceId {
                 bus: virtual_device_config.bus.unwrap_or( Bus::USB ),
                 vendor: virtual_device_config.vendor.unwrap_or( 0x16c0 ),
-                product: virtual_device_config.product.unwrap_or_else( || {
-                    match virtual_device_config.preset {
-                        Some( crate::config::DevicePreset::Keyboard ) => 0x27db,
-                        Some( crate::config::DevicePreset::Mouse ) => 0x27da,
-                        None => 0x27d9
-                    }
+                product: virtual_device_config.product.unwrap_or( match virtual_device_config.preset {
+                    Some( crate::config::DevicePreset::Keyboard ) => 0x27db,
+                    Some( crate::config::DevicePreset::Mouse ) => 0x27da,
+                    None => 0x27d9
                 }),
                 version: virtual_device_config.version.unwrap_or( 0x0111 )
             };
 
-            let name = virtual_device_config.name.as_ref().map( |name| name.as_str() ).unwrap_or( &virtual_device_name );
+            let name = virtual_device_config.name.as_deref().unwrap_or( virtual_device_name );
             let mut event_bits = Vec::new();
+            let mut extra_bits = Vec::new();
 
             match virtual_device_config.preset {
                 Some( crate::config::DevicePreset::Keyboard ) => {
-                    use Key::*;
-                    let keys = &[
-                        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
-                        F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
-                        Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9,
-                        Grave, LeftBrace, RightBrace,
-                        Down, Left, Right, Up,
-                        Backspace, Enter, Space, Tab, Escape,
-                        LeftAlt, RightAlt,
-                        LeftShift, RightShift,
-                        LeftCtrl, RightCtrl,
-                        LeftMeta, RightMeta,
-                        Apostrophe, Backslash, Comma, Dot, Equal, Minus, Semicolon, Slash,
-                        Delete, End, Home, Insert, PageDown, PageUp,
-                        Pause, SysRq,
-                        CapsLock, NumLock, ScrollLock,
-                        Keypad0, Keypad1, Keypad2, Keypad3, Keypad4, Keypad5, Keypad6, Keypad7, Keypad8, Keypad9,
-                        KeypadAsterisk, KeypadComma, KeypadDot, KeypadEnter, KeypadEqual, KeypadMinus, KeypadPlus, KeypadSlash
-                    ];
-
-                    for &key in keys.iter() {
+                    for &key in crate::config::DevicePreset::Keyboard.keys() {
                         event_bits.push( EventBit::Key( key ) );
                     }
+
+                    // NumLock, CapsLock, ScrollLock, Compose and Kana.
+                    for code in 0x00..=0x04 {
+                        extra_bits.push( (EventKind::LED, code) );
+                    }
                 },
                 Some( crate::config::DevicePreset::Mouse ) => {
-                    event_bits.push( EventBit::Key( Key::MouseLeft ) );
-                    event_bits.push( EventBit::Key( Key::MouseRight ) );
-                    event_bits.push( EventBit::Key( Key::MouseMiddle ) );
-                    event_bits.push( EventBit::Key( Key::MouseExtra1 ) );
-                    event_bits.push( EventBit::Key( Key::MouseExtra2 ) );
-                    event_bits.push( EventBit::Key( Key::MouseExtra3 ) );
-                    event_bits.push( EventBit::Key( Key::MouseExtra4 ) );
-                    event_bits.push( EventBit::Key( Key::MouseExtra5 ) );
+                    for &key in crate::config::DevicePreset::Mouse.keys() {
+                        event_bits.push( EventBit::Key( key ) );
+                    }
+
                     event_bits.push( EventBit::RelativeAxis( RelativeAxis::X ) );
                     event_bits.push( EventBit::RelativeAxis( RelativeAxis::Y ) );
                     event_bits.push( EventBit::RelativeAxis( RelativeAxis::Wheel ) );
+                    event_bits.push( EventBit::RelativeAxis( RelativeAxis::WheelHiRes ) );
+                    event_bits.push( EventBit::RelativeAxis( RelativeAxis::Other( crate::event::REL_HWHEEL ) ) );
+                    event_bits.push( EventBit::RelativeAxis( RelativeAxis::Other( crate::event::REL_HWHEEL_HI_RES ) ) );
                 },
                 None => {}
             }
@@ -962,11 +2401,73 @@ impl GlobalState {
                 event_bits.push( EventBit::ForceFeedback( bit ) );
             }
 
-            let device = linux_input::VirtualDevice::create( id, name, event_bits )
-                .expect( "failed to create a virtual device" );
+            for &code in &virtual_device_config.led_bits {
+                if !extra_bits.contains( &(EventKind::LED, code) ) {
+                    extra_bits.push( (EventKind::LED, code) );
+                }
+            }
+
+            for &code in &virtual_device_config.switch_bits {
+                extra_bits.push( (EventKind::Switch, code) );
+            }
+
+            if virtual_device_config.scancodes {
+                extra_bits.push( (EventKind::Misc, crate::event::MSC_SCAN) );
+            }
+
+            if virtual_device_config.repeat.is_some() {
+                extra_bits.push( (EventKind::AutoRepeat, 0) );
+            }
+
+            let has_leds = extra_bits.iter().any( |&(kind, _)| kind == EventKind::LED );
+            let device = match VirtualDevice::create( id, name, &event_bits, &extra_bits ) {
+                Ok( device ) => device,
+                Err( error ) => {
+                    log::error!( "Failed to create the '{}' virtual device: {}", virtual_device_name, error );
+                    continue;
+                }
+            };
+
+            if let Some( repeat ) = virtual_device_config.repeat {
+                if let Err( error ) = device.set_autorepeat( repeat.delay, repeat.period ) {
+                    log::warn!( "Failed to set the autorepeat of '{}': {}", virtual_device_name, error );
+                }
+            }
+
+            let path = match device.path() {
+                Ok( path ) => path,
+                Err( error ) => {
+                    log::error!( "Failed to find the device node of the '{}' virtual device: {}", virtual_device_name, error );
+                    continue;
+                }
+            };
+
+            created_devices.push( (virtual_device_name, virtual_device_config, name, has_leds, device, path) );
+        }
+
+        let mut pending: HashSet< PathBuf > = created_devices.iter().map( |(_, _, _, _, _, path)| path.clone() ).collect();
+        wait_for_udev_add_events( &mut monitor, &mut pending, VIRTUAL_DEVICE_UDEV_TIMEOUT );
+
+        let mut virtual_device_by_rdev = HashMap::new();
+        let mut virtual_device_by_internal_name = HashMap::new();
+        for (virtual_device_name, virtual_device_config, name, has_leds, device, path) in created_devices {
+            if pending.contains( &path ) {
+                log::warn!( "Timed out waiting for udev to announce the '{}' virtual device ({:?})", virtual_device_name, path );
+            }
+
+            let rdev = match get_rdev( &path ) {
+                Ok( rdev ) => rdev,
+                Err( error ) => {
+                    log::error!( "Failed to stat the '{}' virtual device ({:?}): {}", virtual_device_name, path, error );
+                    continue;
+                }
+            };
 
-            let path = device.path().unwrap();
-            let rdev = get_rdev( &path ).unwrap();
+            // Only done once udev is done with the node; otherwise its rules could override our permissions.
+            if let Err( error ) = permissions::apply( &path, virtual_device_config.chmod, virtual_device_config.owner, virtual_device_config.group, &virtual_device_config.acl ) {
+                log::error!( "Failed to set the permissions of the '{}' virtual device: {}", virtual_device_name, error );
+                return None;
+            }
 
             log::info!( "Created a new virtual device: '{}' ({:?})", virtual_device_name, path );
             let virtual_device = VirtualDeviceState {
@@ -974,36 +2475,47 @@ impl GlobalState {
                 internal_name: virtual_device_name.clone(),
                 rdev,
                 path,
-                device
+                device,
+                force_feedback_targets: virtual_device_config.redirect_force_feedback_to.clone(),
+                force_feedback_policy: virtual_device_config.force_feedback_policy,
+                force_feedback: Mutex::new( ForceFeedbackState::default() ),
+                mirror_leds_to: virtual_device_config.mirror_leds_to.clone(),
+                has_autorepeat: virtual_device_config.repeat.is_some(),
+                pressed_keys: HeldKeys::default(),
+                leds: Mutex::new( HashMap::new() )
             };
 
             let virtual_device = Arc::new( virtual_device );
             virtual_device_by_rdev.insert( rdev, virtual_device.clone() );
             virtual_device_by_internal_name.insert( virtual_device_name.clone(), virtual_device.clone() );
 
-            if !virtual_device_config.ff_bits.is_empty() {
-                if let Some( ref target ) = virtual_device_config.redirect_force_feedback_to {
-                    let target = target.clone();
-                    std::thread::spawn( move || virtual_device.force_feedback_thread_main( target ) );
-                }
-            }
-        }
+            let hook = if virtual_device_config.ff_bits.is_empty() {
+                None
+            } else {
+                force_feedback_hooks.remove( virtual_device_name ).map( |hook| ScriptInstance::new( &global_state, hook, name, &[], None ) )
+            };
 
-        std::thread::sleep( std::time::Duration::from_millis( 500 ) );
-        for (virtual_device_name, virtual_device_config) in &global_state.config.virtual_devices {
-            if let Some( chmod ) = virtual_device_config.chmod {
-                let virtual_device = virtual_device_by_internal_name.get( virtual_device_name ).unwrap();
-                use std::os::unix::fs::PermissionsExt;
-                if let Err( error ) = std::fs::set_permissions( &virtual_device.path, std::fs::Permissions::from_mode( chmod as u32 ) ) {
-                    log::error!( "Failed to chmod the '{}' ({:?}) virtual device to {:04o}: {}", virtual_device_name, virtual_device.path, chmod, error );
-                    return None;
-                }
+            let has_force_feedback = !virtual_device_config.ff_bits.is_empty() && (!virtual_device.force_feedback_targets.is_empty() || hook.is_some());
+            if has_force_feedback || has_leds {
+                std::thread::spawn( move || virtual_device.thread_main( hook ) );
             }
         }
 
         *global_state.virtual_device_by_rdev.write() = virtual_device_by_rdev;
         *global_state.virtual_device_by_internal_name.write() = virtual_device_by_internal_name;
 
+        for emulation in &global_state.emulations {
+            let global_state = Arc::downgrade( &global_state );
+            let emulation = emulation.clone();
+            std::thread::spawn( move || emulation_thread_main( global_state, emulation ) );
+        }
+
+        for remap in global_state.remaps.iter().filter( |remap| remap.generates_repeats() ) {
+            let global_state = Arc::downgrade( &global_state );
+            let remap = remap.clone();
+            std::thread::spawn( move || repeat_thread_main( global_state, remap ) );
+        }
+
         Some( global_state )
     }
 
@@ -1021,7 +2533,73 @@ impl GlobalState {
         }
     }
 
+    /// Switches the pointer profile of every `[[pointer]]` entry which applies to the given device filter.
+    fn set_pointer_profile( &self, device: &str, profile_name: &str ) -> Result< (), String > {
+        let profile = self.config.pointer_profiles.get( profile_name ).ok_or_else( || format!( "set_pointer_profile: no such profile: '{}'", profile_name ) )?;
+        let mut found = false;
+        for pointer in self.pointers.iter().filter( |pointer| pointer.device == device ) {
+            pointer.set_profile( profile_name, profile );
+            found = true;
+        }
+
+        if !found {
+            return Err( format!( "set_pointer_profile: '{}' has no [[pointer]] entry", device ) );
+        }
+
+        Ok(())
+    }
+
+    /// Releases the keys held down on the virtual devices by the given physical device, or every one of them.
+    fn release_held_keys( &self, source: Option< usize > ) {
+        let virtual_devices: Vec< _ > = self.virtual_device_by_internal_name.read().values().cloned().collect();
+        for virtual_device in virtual_devices {
+            virtual_device.release_keys( source );
+        }
+    }
+
+    fn restore_permissions( &self ) {
+        let devices: Vec< _ > = self.devices_by_internal_name.read().values().flat_map( |devices| devices.iter() ).cloned().collect();
+        for device in devices {
+            device.restore_permissions( self );
+        }
+
+        self.journal.clear();
+    }
+
+    /// Sets a LED on a virtual device, or on every physical device matched by the given device filter.
+    fn set_led( &self, device: &str, code: u16, is_on: bool ) -> Result< (), String > {
+        if let Some( virtual_device ) = self.virtual_device_by_internal_name.read().get( device ) {
+            return virtual_device.set_led( code, is_on ).map_err( |error| format!( "set_led: failed to set a LED on '{}': {}", device, error ) );
+        }
+
+        let devices: Vec< _ > = self.devices_by_internal_name.read().get( device ).cloned().unwrap_or_default();
+        if devices.is_empty() {
+            return Err( format!( "set_led: no such device: '{}'", device ) );
+        }
+
+        for device_state in devices {
+            if let Err( error ) = device_state.set_led( code, is_on ) {
+                log::warn!( "Failed to set a LED on device '{}': {}", device_state.info.name, error );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_grab_retry_current( &self, path: &Path, retry_id: usize ) -> bool {
+        self.grab_retries.lock().get( path ) == Some( &retry_id )
+    }
+
+    fn on_device_removed( &self, path: &Path ) {
+        self.grab_retries.lock().remove( path );
+    }
+
     fn on_new_device( self: &Arc< Self >, path: &Path ) -> bool {
+        if self.grab_retries.lock().contains_key( path ) {
+            log::debug!( "Already retrying to grab {:?}", path );
+            return true;
+        }
+
         let rdev = match get_rdev( path ) {
             Ok( rdev ) => rdev,
             Err( error ) => {
@@ -1034,7 +2612,7 @@ impl GlobalState {
             return false;
         }
 
-        let device_with_info = Device::open( &path ).ok().and_then( |mut device| {
+        let device_with_info = Device::open( path ).ok().and_then( |mut device| {
             let info = DeviceInfo::get( &mut device )?;
             Some( (device, info) )
         });
@@ -1049,76 +2627,389 @@ impl GlobalState {
         log::info!( "Found a new device in {:?}: '{}'", path, info.name );
         let mut exclusive = false;
         let mut chmod = None;
+        let mut owner = None;
+        let mut group = None;
+        let mut acl = Vec::new();
+        let mut emergency = None;
+        let mut on_grab_failure = GrabFailurePolicy::Skip;
         let mut matched_filters = Vec::new();
         for (device_filter_name, device_filter) in &self.config.device_filters {
             if info.match_with_config( device_filter ) {
                 matched_filters.push( device_filter_name.to_owned() );
                 exclusive |= device_filter.exclusive;
+                if device_filter.exclusive {
+                    on_grab_failure = device_filter.on_grab_failure;
+                }
+
                 if let Some( value ) = device_filter.chmod {
                     chmod = Some( value );
                 }
+
+                if let Some( value ) = device_filter.owner {
+                    owner = Some( value );
+                }
+
+                if let Some( value ) = device_filter.group {
+                    group = Some( value );
+                }
+
+                acl.extend( device_filter.acl.iter().cloned() );
+
+                // The combo only matters for the filters which grab the device.
+                if device_filter.exclusive || emergency.is_none() {
+                    emergency = Some( (device_filter.emergency_combo.clone(), device_filter.on_emergency) );
+                }
             }
         }
 
-        if matched_filters.is_empty() {
-            return false;
-        }
+        let (emergency_combo, on_emergency) = match emergency {
+            Some( emergency ) => emergency,
+            None => return false
+        };
 
         log::info!( "  Device '{}' matches the following filters:", info.name );
         for name in &matched_filters {
             log::info!( "    '{}'", name );
         }
 
+        let mut matched = MatchedDevice {
+            path: path.to_owned(),
+            device,
+            info,
+            matched_filters,
+            exclusive,
+            chmod,
+            owner,
+            group,
+            acl,
+            emergency_combo,
+            on_emergency
+        };
+
         if exclusive {
-            if let Err( error ) = device.grab() {
-                log::error!( "  Failed to turn on the exclusive mode for '{}': {}", info.name, error );
-                std::process::exit( 1 );
+            if let Err( error ) = matched.device.grab() {
+                log::error!( "  Failed to turn on the exclusive mode for '{}': {}", matched.info.name, error );
+                match on_grab_failure {
+                    GrabFailurePolicy::Skip => {
+                        log::warn!( "  Skipping '{}'", matched.info.name );
+                        return false;
+                    },
+                    GrabFailurePolicy::NonExclusive => {
+                        log::warn!( "  Falling back to the non-exclusive mode for '{}'", matched.info.name );
+                        matched.exclusive = false;
+                    },
+                    GrabFailurePolicy::Retry { attempts } => {
+                        log::warn!( "  Will retry to grab '{}' up to {} time(s)", matched.info.name, attempts );
+                        let retry_id = NEXT_GRAB_RETRY_ID.fetch_add( 1, Ordering::Relaxed );
+                        self.grab_retries.lock().insert( path.to_owned(), retry_id );
+
+                        let global_state = Arc::downgrade( self );
+                        std::thread::spawn( move || grab_retry_thread_main( global_state, matched, retry_id, attempts ) );
+                        return true;
+                    }
+                }
+            } else {
+                log::info!( "  Exclusive mode turned on for '{}'", matched.info.name );
             }
-
-            log::info!( "  Exclusive mode turned on for '{}'", info.name );
         }
 
+        self.add_device( matched );
+        true
+    }
+
+    fn add_device( self: &Arc< Self >, matched: MatchedDevice ) {
+        let MatchedDevice {
+            path,
+            device,
+            info,
+            matched_filters,
+            exclusive,
+            chmod,
+            owner,
+            group,
+            acl,
+            emergency_combo,
+            on_emergency
+        } = matched;
+        let path = path.as_path();
+        let source_id = NEXT_SOURCE_ID.fetch_add( 1, Ordering::Relaxed );
+
         let mut old_permissions = None;
-        if let Some( chmod ) = chmod {
-            use std::os::unix::fs::PermissionsExt;
-            let metadata = std::fs::metadata( path );
-            if let Err( error ) = std::fs::set_permissions( &path, std::fs::Permissions::from_mode( chmod as u32 ) ) {
-                log::error!( "Failed to chmod the '{}' ({:?}) device to {:04o}: {}", info.name, path, chmod, error );
-            } else {
-                if let Ok( metadata ) = metadata {
-                    old_permissions = Some( metadata.permissions() );
-                }
+        if chmod.is_some() || owner.is_some() || group.is_some() || !acl.is_empty() {
+            match OriginalPermissions::capture( path ) {
+                Ok( original ) => {
+                    // Journaled first so that a crash in the middle of this still gets the node restored.
+                    self.journal.insert( source_id, &original );
+                    match permissions::apply( path, chmod, owner, group, &acl ) {
+                        Ok(()) => old_permissions = Some( original ),
+                        Err( error ) => {
+                            log::error!( "Failed to set the permissions of the '{}' device: {}", info.name, error );
+
+                            // We could have changed something before failing.
+                            if original.is_unchanged() {
+                                self.journal.remove( source_id );
+                            } else {
+                                old_permissions = Some( original );
+                            }
+                        }
+                    }
+                },
+                Err( error ) => log::error!( "Failed to read the permissions of the '{}' ({:?}) device: {}", info.name, path, error )
             }
         }
 
+        let absolute_mapper = self.config.absolute_mappings.iter()
+            .find( |mapping| matched_filters.contains( &mapping.device ) )
+            .and_then( |mapping| {
+                let mapper = AbsoluteMapper::new( mapping, &info.absolute_axis_bits );
+                if mapper.is_none() {
+                    log::warn!( "Device '{}' has no absolute X and Y axes to map", info.name );
+                }
+                mapper
+            });
+
+        let multitouch = Multitouch::new( &info.absolute_axis_bits ).map( Arc::new );
+        let gestures = self.config.gestures.iter()
+            .find( |gestures| matched_filters.contains( &gestures.device ) )
+            .and_then( |gestures| {
+                let recognizer = if multitouch.is_some() { GestureRecognizer::new( gestures, &info.absolute_axis_bits ) } else { None };
+                if recognizer.is_none() {
+                    log::warn!( "Device '{}' doesn't support multitouch; gestures won't be recognized", info.name );
+                }
+                recognizer
+            });
         let mut scripts = Vec::new();
         for script in &self.scripts {
             if matched_filters.contains( &script.device ) {
-                scripts.push( ScriptInstance::new( self, script.clone() ) );
+                scripts.push( ScriptInstance::new( self, script.clone(), &info.name, &info.absolute_axis_bits, multitouch.clone() ) );
+            }
+        }
+
+        let remaps = self.remaps.iter()
+            .filter( |remap| matched_filters.contains( &remap.device ) )
+            .cloned()
+            .collect();
+
+        let merges = self.config.merges.iter()
+            .filter_map( |merge| {
+                let target_axes = &self.config.virtual_devices.get( &merge.target )?.abs_bits;
+                MergeInput::new( merge, &info.name, &matched_filters, &info.absolute_axis_bits, target_axes )
+            })
+            .collect();
+
+        let emulations = self.emulations.iter()
+            .filter( |emulation| matched_filters.contains( &emulation.device ) )
+            .cloned()
+            .collect();
+
+        let pointer = self.pointers.iter()
+            .find( |pointer| matched_filters.contains( &pointer.device ) )
+            .map( |pointer| PointerMotion::new( pointer.clone() ) );
+
+        let button_scrolls = self.button_scrolls.iter()
+            .filter( |button_scroll| matched_filters.contains( &button_scroll.device ) )
+            .cloned()
+            .collect();
+
+        let mut force_feedback = None;
+        if !info.force_feedback_bits.is_empty() {
+            match ForceFeedbackDevice::open( path ) {
+                Ok( device ) => force_feedback = Some( device ),
+                Err( error ) => log::warn!( "  Failed to open '{}' for force feedback: {}", info.name, error )
             }
         }
 
         let state = DeviceState {
             global_state: Arc::downgrade( self ),
+            source_id,
             path: path.to_owned(),
             device,
+            force_feedback,
             info,
             matched_filters,
             scripts,
-            old_permissions,
-            exclusive
+            remaps,
+            merges,
+            emulations,
+            pointer,
+            button_scrolls,
+            multitouch,
+            gestures,
+            absolute_mapper,
+            pending_scancode: Mutex::new( None ),
+            merged_targets: Mutex::new( Vec::new() ),
+            remapped_targets: Mutex::new( Vec::new() ),
+            old_permissions: Mutex::new( old_permissions ),
+            emergency_combo,
+            on_emergency,
+            is_grabbed: AtomicBool::new( exclusive ),
+            is_paused: AtomicBool::new( false ),
+            connected_at: Instant::now()
         };
 
         let state = Arc::new( state );
         {
             let mut devices_by_internal_name = self.devices_by_internal_name.write();
             for internal_name in &state.matched_filters {
-                devices_by_internal_name.entry( internal_name.clone() ).or_insert_with( Vec::new ).push( state.clone() );
+                devices_by_internal_name.entry( internal_name.clone() ).or_default().push( state.clone() );
+            }
+        }
+
+        let virtual_devices: Vec< _ > = self.virtual_device_by_internal_name.read().values().cloned().collect();
+        for virtual_device in virtual_devices {
+            if state.force_feedback.is_some() {
+                virtual_device.on_force_feedback_device_connected( &state );
             }
+
+            virtual_device.on_led_device_connected( &state );
         }
 
         std::thread::spawn( move || state.thread_main() );
-        true
+    }
+}
+
+/// Waits until udev announces every one of the given device nodes, removing them from the set as they show up.
+fn wait_for_udev_add_events( monitor: &mut udev::MonitorSocket, pending: &mut HashSet< PathBuf >, timeout: std::time::Duration ) {
+    use std::os::unix::io::AsRawFd;
+
+    let deadline = Instant::now() + timeout;
+    while !pending.is_empty() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+
+        match linux_input::poll_read( monitor.as_raw_fd(), Some( deadline - now ) ) {
+            Ok( true ) => {},
+            Ok( false ) => continue,
+            Err( error ) => {
+                log::warn!( "Failed to wait for udev events: {}", error );
+                break;
+            }
+        }
+
+        for event in monitor.by_ref() {
+            if event.event_type() != udev::EventType::Add {
+                continue;
+            }
+
+            if let Some( devnode ) = event.devnode() {
+                pending.remove( devnode );
+            }
+        }
+    }
+}
+
+fn grab_retry_thread_main( global_state: Weak< GlobalState >, matched: MatchedDevice, retry_id: usize, attempts: u32 ) {
+    // Stops as soon as the device was removed or a newer retry took over its path.
+    let is_current = || {
+        RUNNING.load( Ordering::Relaxed ) &&
+        global_state.upgrade().map( |global_state| global_state.is_grab_retry_current( &matched.path, retry_id ) ).unwrap_or( false )
+    };
+
+    let mut delay = GRAB_RETRY_INITIAL_DELAY;
+    for attempt in 1..=attempts {
+        let deadline = Instant::now() + delay;
+        while Instant::now() < deadline {
+            if !is_current() {
+                return;
+            }
+
+            std::thread::sleep( std::cmp::min( deadline - Instant::now(), std::time::Duration::from_millis( 100 ) ) );
+        }
+
+        if !is_current() {
+            return;
+        }
+
+        let global_state = match global_state.upgrade() {
+            Some( global_state ) => global_state,
+            None => return
+        };
+
+        match matched.device.grab() {
+            Ok(()) => {
+                log::info!( "Exclusive mode turned on for '{}' after {} retry attempt(s)", matched.info.name, attempt );
+                global_state.grab_retries.lock().remove( &matched.path );
+                global_state.add_device( matched );
+                return;
+            },
+            Err( ref error ) if error.as_errno() == Some( nix::errno::Errno::ENODEV ) => {
+                log::info!( "Device '{}' was disconnected; no longer trying to grab it", matched.info.name );
+                global_state.grab_retries.lock().remove( &matched.path );
+                return;
+            },
+            Err( error ) => {
+                log::warn!( "Retry attempt {}/{} to grab '{}' failed: {}", attempt, attempts, matched.info.name, error );
+                if attempt == attempts {
+                    log::error!( "Giving up on grabbing '{}'; skipping it", matched.info.name );
+                    global_state.grab_retries.lock().remove( &matched.path );
+                }
+            }
+        }
+
+        delay = std::cmp::min( delay * 2, GRAB_RETRY_MAX_DELAY );
+    }
+}
+
+fn emulation_thread_main( global_state: Weak< GlobalState >, emulation: Arc< Emulation > ) {
+    let mut output = Vec::new();
+    let mut last_tick = Instant::now();
+    while RUNNING.load( Ordering::Relaxed ) {
+        if !emulation.is_active() {
+            // Parked until there's something to emulate; the time spent idle isn't accounted for.
+            if emulation.wait_until_active( std::time::Duration::from_millis( 100 ) ) {
+                last_tick = Instant::now();
+            }
+
+            continue;
+        }
+
+        std::thread::sleep( emulation.tick );
+
+        let now = Instant::now();
+        emulation.tick( now - last_tick, &mut output );
+        last_tick = now;
+
+        if output.is_empty() {
+            continue;
+        }
+
+        let global_state = match global_state.upgrade() {
+            Some( global_state ) => global_state,
+            None => break
+        };
+
+        if let Some( device ) = global_state.lookup_device_by_internal_name( &emulation.target ) {
+            for body in output.drain( .. ) {
+                if let Err( error ) = device.emit( body ) {
+                    log::error!( "Failed to send an event to '{}': {}", emulation.target, error );
+                }
+            }
+        }
+    }
+}
+
+fn repeat_thread_main( global_state: Weak< GlobalState >, remap: Arc< RemapTable > ) {
+    while RUNNING.load( Ordering::Relaxed ) {
+        let key = match remap.wait_for_repeat( std::time::Duration::from_millis( 100 ) ) {
+            Some( key ) => key,
+            None => continue
+        };
+
+        let global_state = match global_state.upgrade() {
+            Some( global_state ) => global_state,
+            None => break
+        };
+
+        if let Some( device ) = global_state.lookup_device_by_internal_name( &remap.target ) {
+            let repeat = linux_input::InputEventBody::Other { kind: EventKind::Key, code: key.raw(), value: 2 };
+            for body in &[repeat, linux_input::InputEventBody::Flush] {
+                if let Err( error ) = device.emit( body ) {
+                    log::error!( "Failed to send an event to '{}': {}", remap.target, error );
+                }
+            }
+        }
     }
 }
 
@@ -1144,7 +3035,8 @@ fn run( config: Config ) {
 
     use std::os::unix::io::AsRawFd;
     while RUNNING.load( Ordering::Relaxed ) {
-        if linux_input::poll_read( monitor.as_raw_fd(), None ).unwrap() {
+        // The signal can be delivered to any thread, so we can't rely on it interrupting the poll.
+        if linux_input::poll_read( monitor.as_raw_fd(), Some( std::time::Duration::from_millis( 500 ) ) ).unwrap() {
             let event = monitor.next().unwrap();
             let devnode = event.devnode();
             log::debug!(
@@ -1173,24 +3065,19 @@ fn run( config: Config ) {
                 },
                 udev::EventType::Remove => {
                     present_devices.remove( devnode );
+                    state.on_device_removed( devnode );
                 },
                 _ => continue
             }
 
-            if event.event_type() == udev::EventType::Add {
-                if state.on_new_device( devnode ) {
-                    present_devices.insert( devnode.to_owned() );
-                }
+            if event.event_type() == udev::EventType::Add && state.on_new_device( devnode ) {
+                present_devices.insert( devnode.to_owned() );
             }
         }
     }
 
-    let devices: Vec< _ > = state.devices_by_internal_name.read().values().flat_map( |devices| devices.iter() ).cloned().collect();
-    for device in devices {
-        if let Some( ref old_permissions ) = device.old_permissions {
-            let _ = std::fs::set_permissions( &device.path, old_permissions.clone() );
-        }
-    }
+    state.release_held_keys( None );
+    state.restore_permissions();
 }
 
 fn set_signal_handler() {
@@ -1200,7 +3087,7 @@ fn set_signal_handler() {
         }
 
         for &signal in &[libc::SIGINT, libc::SIGTERM] {
-            if libc::signal( signal, signal_handler as libc::size_t ) == libc::SIG_ERR {
+            if libc::signal( signal, signal_handler as *const () as libc::size_t ) == libc::SIG_ERR {
                 panic!( "signal failed: {}", std::io::Error::last_os_error() );
             }
         }
@@ -1341,7 +3228,7 @@ fn main() {
                         }
                     }
                     bits_s.sort_by( |(a_raw, a), (b_raw, b)| {
-                        get_category( *a_raw, &a ).cmp( &get_category( *b_raw, &b ) ).then( a.cmp( b ) )
+                        get_category( *a_raw, a ).cmp( &get_category( *b_raw, b ) ).then( a.cmp( b ) )
                     });
                     let mut line = String::new();
                     for (_, bit) in bits_s {
@@ -1363,7 +3250,7 @@ fn main() {
                 if !info.relative_axis_bits.is_empty() {
                     println!( "  Rel. axis: {}", info.relative_axis_bits.len() );
                     for bit in info.relative_axis_bits {
-                        println!( "      {}", bit.to_string() );
+                        println!( "      {}", bit );
                     }
                 }
 
@@ -1372,7 +3259,7 @@ fn main() {
                     for bit in info.absolute_axis_bits {
                         println!(
                             "      {} (min={}, max={}, noise_threshold={}, deadzone={}, resolution={})",
-                            bit.axis.to_string(), bit.minimum, bit.maximum, bit.noise_threshold, bit.deadzone, bit.resolution
+                            bit.axis, bit.minimum, bit.maximum, bit.noise_threshold, bit.deadzone, bit.resolution
                         );
                     }
                 }
@@ -1403,3 +3290,167 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        std::{
+            sync::{
+                mpsc,
+                Arc
+            },
+            time::{
+                Duration
+            }
+        },
+        linux_input::{
+            Key
+        },
+        crate::{
+            config::{
+                self,
+                Config,
+                ErrorPolicy,
+                ScriptLimits
+            },
+            permissions::{
+                RestoreJournal
+            }
+        },
+        super::{
+            EffectCopy,
+            EventPipe,
+            ForceFeedbackState,
+            GlobalState,
+            HeldKeys,
+            Script,
+            ScriptInstance
+        }
+    };
+
+    fn empty_global_state() -> Arc< GlobalState > {
+        Arc::new( GlobalState {
+            config: Config {
+                path: "test.toml".into(),
+                device_filters: Default::default(),
+                virtual_devices: Default::default(),
+                scripts: Vec::new(),
+                remaps: Vec::new(),
+                merges: Vec::new(),
+                emulations: Vec::new(),
+                pointer_profiles: Default::default(),
+                pointers: Vec::new(),
+                button_scrolls: Vec::new(),
+                gestures: Vec::new(),
+                absolute_mappings: Vec::new()
+            },
+            scripts: Vec::new(),
+            remaps: Vec::new(),
+            emulations: Vec::new(),
+            pointers: Vec::new(),
+            button_scrolls: Vec::new(),
+            devices_by_internal_name: Default::default(),
+            virtual_device_by_rdev: Default::default(),
+            virtual_device_by_internal_name: Default::default(),
+            journal: RestoreJournal::new( "/nonexistent/inputd-permissions" ),
+            grab_retries: Default::default()
+        })
+    }
+
+    /// Runs the script for a single key press and returns whether the event was sent out.
+    fn run_script( code: &str ) -> bool {
+        let global_state = empty_global_state();
+        let script = config::Script {
+            device: "Keyboard".into(),
+            code: code.into(),
+            error_limit: 10,
+            error_window: Duration::from_secs( 10 ),
+            on_error_limit: ErrorPolicy::Ungrab,
+            passthrough_to: Some( "Virtual Keyboard".into() ),
+            default_target: None,
+            passthrough: false,
+            limits: ScriptLimits {
+                max_operations: 1000,
+                .. ScriptLimits::default()
+            }
+        };
+
+        let script = Script::compile( &super::create_rhai_engine(), "test.toml".as_ref(), "test script".into(), &script ).unwrap();
+        let instance = ScriptInstance::new( &global_state, Arc::new( script ), "Keyboard", &[], None );
+        instance.script_state.lock().event = linux_input::InputEvent {
+            timestamp: linux_input::Timestamp::default(),
+            body: linux_input::InputEventBody::KeyPress( Key::Escape )
+        };
+
+        assert!( instance.eval() );
+        let is_forwarded = instance.script_state.lock().forwarded;
+        is_forwarded
+    }
+
+    #[test]
+    fn limit_violation_passes_the_event_through() {
+        // Consuming the event doesn't count since the script didn't finish.
+        assert!( run_script( "consume(); loop {}" ) );
+        assert!( run_script( "send_to(\"Virtual Keyboard\"); loop {}" ) );
+        assert!( run_script( "loop {}" ) );
+        assert!( !run_script( "consume();" ) );
+    }
+
+    #[test]
+    fn disconnect_forgets_the_force_feedback_copies() {
+        let copy = |source_id, effect_id| EffectCopy {
+            device_state: std::sync::Weak::new(),
+            source_id,
+            effect_id,
+            gain: 1.0
+        };
+
+        let mut force_feedback = ForceFeedbackState::default();
+        force_feedback.copies.insert( 0, vec![ copy( 1, 10 ), copy( 2, 20 ) ] );
+        force_feedback.copies.insert( 1, vec![ copy( 1, 11 ) ] );
+
+        force_feedback.forget_device( 1 );
+
+        let remaining: Vec< (usize, i16) > = force_feedback.copies[ &0 ].iter().map( |copy| (copy.source_id, copy.effect_id) ).collect();
+        assert_eq!( remaining, [(2, 20)] );
+        assert!( force_feedback.copies[ &1 ].is_empty() );
+    }
+
+    #[test]
+    fn disconnect_releases_the_held_keys() {
+        // Just like in `DeviceState::thread_main` the worker presses the keys and the cleanup releases them once it exits.
+        let held_keys = Arc::new( HeldKeys::default() );
+        let pipe = Arc::new( EventPipe::new() );
+        let (sender, receiver) = mpsc::channel();
+        {
+            let held_keys = held_keys.clone();
+            let pipe = pipe.clone();
+            std::thread::spawn( move || {
+                let mut buffer = Vec::new();
+                while pipe.receive( &mut buffer ) {
+                    for key in buffer.drain( .. ) {
+                        held_keys.press( key, Some( 1 ) );
+                    }
+                }
+
+                sender.send( held_keys.take( Some( 1 ) ) ).unwrap();
+            });
+        }
+
+        held_keys.press( Key::Escape, Some( 2 ) );
+        pipe.push( Key::LeftShift );
+        pipe.push( Key::LeftAlt );
+
+        // The reader got an ENODEV.
+        pipe.close();
+
+        let mut released = receiver.recv_timeout( Duration::from_secs( 5 ) ).expect( "the worker didn't exit" );
+        released.sort_by_key( |key| key.raw() );
+        let mut expected = vec![ Key::LeftShift, Key::LeftAlt ];
+        expected.sort_by_key( |key| key.raw() );
+        assert_eq!( released, expected );
+
+        // The keys held by the other devices are left alone.
+        assert_eq!( held_keys.take( None ), [Key::Escape] );
+    }
+}
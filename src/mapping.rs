@@ -0,0 +1,202 @@
+use {
+    std::{
+        collections::{
+            HashMap
+        }
+    },
+    linux_input::{
+        AbsoluteAxis,
+        AbsoluteAxisBit,
+        InputEvent,
+        InputEventBody
+    },
+    parking_lot::{
+        Mutex
+    },
+    crate::{
+        axis::{
+            denormalize_unit,
+            normalize_unit
+        },
+        config::{
+            AbsoluteMapping,
+            Area
+        },
+        event::{
+            ABS_MT_POSITION_X,
+            ABS_MT_POSITION_Y,
+            ABS_MT_SLOT
+        }
+    }
+};
+
+#[derive(Copy, Clone)]
+struct Point {
+    position: (i32, i32),
+    last_emitted: (Option< i32 >, Option< i32 >)
+}
+
+impl Point {
+    fn new( position: (i32, i32) ) -> Self {
+        Point {
+            position,
+            last_emitted: (None, None)
+        }
+    }
+}
+
+/// The ranges of a pair of X and Y axes.
+#[derive(Copy, Clone)]
+struct Ranges {
+    x: (i32, i32),
+    y: (i32, i32)
+}
+
+struct State {
+    point: Point,
+    current_slot: i32,
+    /// The multitouch contacts, keyed by their slot.
+    slots: HashMap< i32, Point >
+}
+
+/// Applies an `[[absolute-mapping]]` to the absolute X and Y axes of a single device, and to its multitouch contacts.
+pub struct AbsoluteMapper {
+    rotation: u16,
+    area: Area,
+    region: Area,
+    ranges: Ranges,
+    /// The ranges and the initial position of the multitouch axes, if the device has them.
+    multitouch: Option< (Ranges, (i32, i32)) >,
+    state: Mutex< State >
+}
+
+fn physical_size( bit: &AbsoluteAxisBit ) -> f64 {
+    (bit.maximum as f64 - bit.minimum as f64) / bit.resolution.max( 1 ) as f64
+}
+
+/// Shrinks the area around its center so that it has the given aspect ratio once rotated.
+fn fit_aspect_ratio( area: Area, (width, height): (f64, f64), is_rotated: bool, aspect_ratio: f64 ) -> Area {
+    let (left, top, right, bottom) = area;
+    let (area_width, area_height) = ((right - left) * width, (bottom - top) * height);
+    let (shown_width, shown_height) = if is_rotated { (area_height, area_width) } else { (area_width, area_height) };
+    if shown_width <= 0.0 || shown_height <= 0.0 {
+        return area;
+    }
+
+    let current = shown_width / shown_height;
+    let (horizontal_scale, vertical_scale) = if current > aspect_ratio {
+        (aspect_ratio / current, 1.0)
+    } else {
+        (1.0, current / aspect_ratio)
+    };
+
+    // The scales are in the rotated orientation, while the area is in the device's own one.
+    let (scale_x, scale_y) = if is_rotated { (vertical_scale, horizontal_scale) } else { (horizontal_scale, vertical_scale) };
+    let (center_x, center_y) = ((left + right) / 2.0, (top + bottom) / 2.0);
+    let (half_width, half_height) = ((right - left) * scale_x / 2.0, (bottom - top) * scale_y / 2.0);
+    (center_x - half_width, center_y - half_height, center_x + half_width, center_y + half_height)
+}
+
+impl AbsoluteMapper {
+    /// Returns `None` if the device doesn't have both the X and the Y axis.
+    pub fn new( mapping: &AbsoluteMapping, axes: &[AbsoluteAxisBit] ) -> Option< Self > {
+        let x = axes.iter().find( |bit| bit.axis == AbsoluteAxis::X )?;
+        let y = axes.iter().find( |bit| bit.axis == AbsoluteAxis::Y )?;
+        let rotation = (mapping.rotation + if mapping.left_handed { 180 } else { 0 }) % 360;
+        let area = match mapping.aspect_ratio {
+            Some( aspect_ratio ) => fit_aspect_ratio( mapping.area, (physical_size( x ), physical_size( y )), matches!( rotation, 90 | 270 ), aspect_ratio ),
+            None => mapping.area
+        };
+
+        let find_other = |code| axes.iter().find( |bit| bit.axis == AbsoluteAxis::Other( code ) );
+        let multitouch = match (find_other( ABS_MT_POSITION_X ), find_other( ABS_MT_POSITION_Y )) {
+            (Some( x ), Some( y )) => Some( (
+                Ranges { x: (x.minimum, x.maximum), y: (y.minimum, y.maximum) },
+                (x.initial_value, y.initial_value)
+            )),
+            _ => None
+        };
+
+        Some( AbsoluteMapper {
+            rotation,
+            area,
+            region: mapping.region,
+            ranges: Ranges { x: (x.minimum, x.maximum), y: (y.minimum, y.maximum) },
+            multitouch,
+            state: Mutex::new( State {
+                point: Point::new( (x.initial_value, y.initial_value) ),
+                current_slot: find_other( ABS_MT_SLOT ).map( |bit| bit.initial_value ).unwrap_or( 0 ),
+                slots: HashMap::new()
+            })
+        })
+    }
+
+    fn map( &self, (x, y): (i32, i32), ranges: Ranges ) -> (i32, i32) {
+        let (left, top, right, bottom) = self.area;
+        let u = ((normalize_unit( x, ranges.x.0, ranges.x.1 ) - left) / (right - left)).clamp( 0.0, 1.0 );
+        let v = ((normalize_unit( y, ranges.y.0, ranges.y.1 ) - top) / (bottom - top)).clamp( 0.0, 1.0 );
+        let (u, v) = match self.rotation {
+            90 => (1.0 - v, u),
+            180 => (1.0 - u, 1.0 - v),
+            270 => (v, 1.0 - u),
+            _ => (u, v)
+        };
+
+        let (left, top, right, bottom) = self.region;
+        (
+            denormalize_unit( left + u * (right - left), ranges.x.0, ranges.x.1 ),
+            denormalize_unit( top + v * (bottom - top), ranges.y.0, ranges.y.1 )
+        )
+    }
+
+    fn emit( &self, event: &InputEvent, point: &mut Point, ranges: Ranges, axes: (AbsoluteAxis, AbsoluteAxis), output: &mut Vec< InputEvent > ) {
+        let (x, y) = self.map( point.position, ranges );
+        if point.last_emitted.0 != Some( x ) {
+            point.last_emitted.0 = Some( x );
+            output.push( InputEvent { timestamp: event.timestamp, body: InputEventBody::AbsoluteMove { axis: axes.0, position: x } } );
+        }
+        if point.last_emitted.1 != Some( y ) {
+            point.last_emitted.1 = Some( y );
+            output.push( InputEvent { timestamp: event.timestamp, body: InputEventBody::AbsoluteMove { axis: axes.1, position: y } } );
+        }
+    }
+
+    /// Translates the absolute X and Y axes, and the positions of the multitouch contacts.
+    ///
+    /// Since a rotated axis depends on both of them the output may contain the other axis.
+    /// Returns `false` and leaves `output` untouched if the event isn't affected.
+    pub fn translate( &self, event: &InputEvent, output: &mut Vec< InputEvent > ) -> bool {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        match event.body {
+            InputEventBody::AbsoluteMove { axis: AbsoluteAxis::X, position } => state.point.position.0 = position,
+            InputEventBody::AbsoluteMove { axis: AbsoluteAxis::Y, position } => state.point.position.1 = position,
+            InputEventBody::AbsoluteMove { axis: AbsoluteAxis::Other( ABS_MT_SLOT ), position } => {
+                // Only tracked; the event itself goes through as it is.
+                state.current_slot = position;
+                return false;
+            },
+            InputEventBody::AbsoluteMove { axis: AbsoluteAxis::Other( axis @ (ABS_MT_POSITION_X | ABS_MT_POSITION_Y) ), position } => {
+                let (ranges, initial_position) = match self.multitouch {
+                    Some( multitouch ) => multitouch,
+                    None => return false
+                };
+
+                let point = state.slots.entry( state.current_slot ).or_insert_with( || Point::new( initial_position ) );
+                if axis == ABS_MT_POSITION_X {
+                    point.position.0 = position;
+                } else {
+                    point.position.1 = position;
+                }
+
+                let axes = (AbsoluteAxis::Other( ABS_MT_POSITION_X ), AbsoluteAxis::Other( ABS_MT_POSITION_Y ));
+                self.emit( event, point, ranges, axes, output );
+                return true;
+            },
+            _ => return false
+        }
+
+        self.emit( event, &mut state.point, self.ranges, (AbsoluteAxis::X, AbsoluteAxis::Y), output );
+        true
+    }
+}
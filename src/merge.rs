@@ -0,0 +1,154 @@
+use {
+    std::{
+        collections::{
+            HashMap
+        },
+        sync::{
+            atomic::{
+                AtomicI32,
+                Ordering
+            }
+        }
+    },
+    linux_input::{
+        AbsoluteAxis,
+        AbsoluteAxisBit,
+        EventKind,
+        InputEventBody,
+        Key
+    },
+    crate::{
+        config::{
+            AxisTransform,
+            Merge
+        }
+    }
+};
+
+struct AxisMapping {
+    to: AbsoluteAxis,
+    source: (i32, i32),
+    target: (i32, i32),
+    transform: AxisTransform,
+    /// Needed to apply the radial deadzone when only the other axis moves.
+    last_position: AtomicI32
+}
+
+impl AxisMapping {
+    fn output( &self, value: f64 ) -> InputEventBody {
+        InputEventBody::AbsoluteMove {
+            axis: self.to,
+            position: self.transform.output( value, self.target )
+        }
+    }
+}
+
+/// The part of a `[[merge]]` entry which applies to a single physical device.
+pub struct MergeInput {
+    pub target: String,
+    keys: HashMap< Key, Key >,
+    axes: HashMap< AbsoluteAxis, AxisMapping >
+}
+
+impl MergeInput {
+    /// Resolves the mappings for a device which matches the given filters.
+    ///
+    /// Returns `None` if none of the mappings apply to the device.
+    pub fn new( merge: &Merge, device_name: &str, matched_filters: &[String], source_axes: &[AbsoluteAxisBit], target_axes: &[AbsoluteAxisBit] ) -> Option< Self > {
+        let mut keys = HashMap::new();
+        for key in &merge.keys {
+            if matched_filters.contains( &key.device ) {
+                keys.insert( key.from, key.to );
+            }
+        }
+
+        let mut axes = HashMap::new();
+        for axis in &merge.axes {
+            if !matched_filters.contains( &axis.device ) {
+                continue;
+            }
+
+            let target = match target_axes.iter().find( |bit| bit.axis == axis.to ) {
+                Some( bit ) => (bit.minimum, bit.maximum),
+                None => continue
+            };
+
+            let source = match axis.range {
+                Some( range ) => range,
+                None => match source_axes.iter().find( |bit| bit.axis == axis.from ) {
+                    Some( bit ) => (bit.minimum, bit.maximum),
+                    None => {
+                        log::warn!( "Device '{}' has no {} axis to merge into '{}'", device_name, axis.from, merge.target );
+                        continue;
+                    }
+                }
+            };
+
+            axes.insert( axis.from, AxisMapping {
+                to: axis.to,
+                source,
+                target,
+                transform: axis.transform.clone(),
+                last_position: AtomicI32::new( source.0 / 2 + source.1 / 2 )
+            });
+        }
+
+        if keys.is_empty() && axes.is_empty() {
+            return None;
+        }
+
+        Some( MergeInput {
+            target: merge.target.clone(),
+            keys,
+            axes
+        })
+    }
+
+    /// Translates an event according to the mappings.
+    ///
+    /// Returns `false` and leaves `output` untouched if the event isn't mapped.
+    pub fn translate( &self, body: &InputEventBody, output: &mut Vec< InputEventBody > ) -> bool {
+        let body = match *body {
+            InputEventBody::KeyPress( key ) => match self.keys.get( &key ) {
+                Some( &key ) => InputEventBody::KeyPress( key ),
+                None => return false
+            },
+            InputEventBody::KeyRelease( key ) => match self.keys.get( &key ) {
+                Some( &key ) => InputEventBody::KeyRelease( key ),
+                None => return false
+            },
+            InputEventBody::Other { kind: EventKind::Key, code, value } => match self.keys.get( &Key::from( code ) ) {
+                Some( key ) => InputEventBody::Other { kind: EventKind::Key, code: key.raw(), value },
+                None => return false
+            },
+            InputEventBody::AbsoluteMove { axis, position } => {
+                let mapping = match self.axes.get( &axis ) {
+                    Some( mapping ) => mapping,
+                    None => return false
+                };
+
+                mapping.last_position.store( position, Ordering::Relaxed );
+                let transform = &mapping.transform;
+                let value = transform.input( position, mapping.source );
+                match transform.radial_with.and_then( |other_axis| self.axes.get( &other_axis ) ) {
+                    Some( other ) => {
+                        let other_value = other.transform.input( other.last_position.load( Ordering::Relaxed ), other.source );
+                        let (value, other_value) = crate::axis::radial_deadzone( value, other_value, transform.deadzone, transform.outer_deadzone );
+                        output.push( mapping.output( value ) );
+                        output.push( other.output( other_value ) );
+                    },
+                    None => {
+                        let value = crate::axis::deadzone( value, transform.deadzone, transform.outer_deadzone );
+                        output.push( mapping.output( value ) );
+                    }
+                }
+
+                return true;
+            },
+            _ => return false
+        };
+
+        output.push( body );
+        true
+    }
+}
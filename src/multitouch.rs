@@ -0,0 +1,288 @@
+use {
+    std::{
+        collections::{
+            BTreeMap
+        }
+    },
+    linux_input::{
+        AbsoluteAxis,
+        AbsoluteAxisBit,
+        InputEventBody,
+        Key
+    },
+    parking_lot::{
+        Mutex
+    },
+    crate::{
+        event::{
+            ABS_MT_POSITION_X,
+            ABS_MT_POSITION_Y,
+            ABS_MT_PRESSURE,
+            ABS_MT_SLOT,
+            ABS_MT_TRACKING_ID,
+            ABS_PRESSURE,
+            BTN_TOOL_DOUBLETAP,
+            BTN_TOOL_FINGER,
+            BTN_TOOL_QUADTAP,
+            BTN_TOOL_QUINTTAP,
+            BTN_TOOL_TRIPLETAP,
+            BTN_TOUCH
+        }
+    }
+};
+
+/// Devices can't report more slots than this.
+const MAXIMUM_SLOT_COUNT: usize = 64;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Contact {
+    pub slot: i32,
+    pub tracking_id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub pressure: i32
+}
+
+impl Contact {
+    pub fn to_script_map( self ) -> rhai::Map {
+        let mut map = rhai::Map::new();
+        map.insert( "slot".into(), self.slot.into() );
+        map.insert( "tracking_id".into(), self.tracking_id.into() );
+        map.insert( "x".into(), self.x.into() );
+        map.insert( "y".into(), self.y.into() );
+        map.insert( "pressure".into(), self.pressure.into() );
+        map
+    }
+
+    /// Parses a contact passed from a script; only the `slot` is mandatory.
+    pub fn from_script_map( map: &rhai::Map ) -> Option< Self > {
+        let get = |key: &str| map.get( key ).and_then( |value| value.as_int().ok() );
+        let slot = get( "slot" ).filter( |&slot| slot >= 0 && (slot as usize) < MAXIMUM_SLOT_COUNT )?;
+        Some( Contact {
+            slot,
+            tracking_id: get( "tracking_id" ).unwrap_or( slot ),
+            x: get( "x" ).unwrap_or( 0 ),
+            y: get( "y" ).unwrap_or( 0 ),
+            pressure: get( "pressure" ).unwrap_or( 0 )
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Slot {
+    tracking_id: i32,
+    x: i32,
+    y: i32,
+    pressure: i32
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        Slot {
+            tracking_id: -1,
+            x: 0,
+            y: 0,
+            pressure: 0
+        }
+    }
+}
+
+struct State {
+    current_slot: usize,
+    slots: Vec< Slot >,
+    is_dirty: bool,
+    /// The contacts as of the end of the last frame.
+    frame: Vec< Contact >
+}
+
+/// Tracks the slots of a device which uses the multitouch protocol B.
+pub struct Multitouch {
+    state: Mutex< State >
+}
+
+impl Multitouch {
+    /// Returns `None` if the device doesn't report multitouch slots.
+    pub fn new( axes: &[AbsoluteAxisBit] ) -> Option< Self > {
+        let slot_axis = axes.iter().find( |bit| bit.axis == AbsoluteAxis::Other( ABS_MT_SLOT ) )?;
+        let slot_count = (slot_axis.maximum.max( 0 ) as usize + 1).min( MAXIMUM_SLOT_COUNT );
+        Some( Multitouch {
+            state: Mutex::new( State {
+                current_slot: (slot_axis.initial_value.max( 0 ) as usize).min( slot_count - 1 ),
+                slots: vec![ Slot::default(); slot_count ],
+                is_dirty: false,
+                frame: Vec::new()
+            })
+        })
+    }
+
+    pub fn feed( &self, body: &InputEventBody ) {
+        let mut state = self.state.lock();
+        let (axis, position) = match *body {
+            InputEventBody::AbsoluteMove { axis: AbsoluteAxis::Other( axis ), position } => (axis, position),
+            InputEventBody::Flush => {
+                if state.is_dirty {
+                    state.is_dirty = false;
+                    state.frame = state.slots.iter().enumerate()
+                        .filter( |(_, slot)| slot.tracking_id >= 0 )
+                        .map( |(index, slot)| Contact {
+                            slot: index as i32,
+                            tracking_id: slot.tracking_id,
+                            x: slot.x,
+                            y: slot.y,
+                            pressure: slot.pressure
+                        })
+                        .collect();
+                }
+                return;
+            },
+            _ => return
+        };
+
+        if axis == ABS_MT_SLOT {
+            if position >= 0 && (position as usize) < state.slots.len() {
+                state.current_slot = position as usize;
+            }
+            return;
+        }
+
+        let index = state.current_slot;
+        let slot = &mut state.slots[ index ];
+        match axis {
+            ABS_MT_TRACKING_ID => slot.tracking_id = position,
+            ABS_MT_POSITION_X => slot.x = position,
+            ABS_MT_POSITION_Y => slot.y = position,
+            ABS_MT_PRESSURE => slot.pressure = position,
+            _ => return
+        }
+
+        state.is_dirty = true;
+    }
+
+    /// Returns the contacts as of the end of the last frame.
+    pub fn contacts( &self ) -> Vec< Contact > {
+        self.state.lock().frame.clone()
+    }
+}
+
+struct EmittedContact {
+    /// The tracking ID of the contact as passed by the script.
+    source_tracking_id: i32,
+    x: i32,
+    y: i32,
+    pressure: i32
+}
+
+fn abs( axis: u16, position: i32 ) -> InputEventBody {
+    InputEventBody::AbsoluteMove { axis: AbsoluteAxis::Other( axis ), position }
+}
+
+fn tool_for_count( count: usize ) -> Option< u16 > {
+    match count {
+        0 => None,
+        1 => Some( BTN_TOOL_FINGER ),
+        2 => Some( BTN_TOOL_DOUBLETAP ),
+        3 => Some( BTN_TOOL_TRIPLETAP ),
+        4 => Some( BTN_TOOL_QUADTAP ),
+        _ => Some( BTN_TOOL_QUINTTAP )
+    }
+}
+
+/// Generates the multitouch protocol B events for a virtual device.
+#[derive(Default)]
+pub struct TouchEmitter {
+    slots: BTreeMap< i32, EmittedContact >,
+    current_slot: Option< i32 >,
+    next_tracking_id: i32
+}
+
+impl TouchEmitter {
+    fn select_slot( &mut self, slot: i32, output: &mut Vec< InputEventBody > ) {
+        if self.current_slot != Some( slot ) {
+            self.current_slot = Some( slot );
+            output.push( abs( ABS_MT_SLOT, slot ) );
+        }
+    }
+
+    /// Emits a single frame which changes the contacts on the device to the given ones.
+    ///
+    /// Contacts are matched by their slot; a different tracking ID in the same slot is a new contact.
+    /// The single touch axes and the `BTN_TOUCH` and `BTN_TOOL_*` keys are emulated too.
+    pub fn emit( &mut self, contacts: &[Contact], output: &mut Vec< InputEventBody > ) {
+        let previous_count = self.slots.len();
+        let lifted: Vec< i32 > = self.slots.keys()
+            .filter( |&&slot| !contacts.iter().any( |contact| contact.slot == slot ) )
+            .cloned()
+            .collect();
+
+        for slot in lifted {
+            self.select_slot( slot, output );
+            output.push( abs( ABS_MT_TRACKING_ID, -1 ) );
+            self.slots.remove( &slot );
+        }
+
+        for contact in contacts {
+            let is_new = self.slots.get( &contact.slot ).map( |emitted| emitted.source_tracking_id != contact.tracking_id ).unwrap_or( true );
+            if is_new {
+                self.select_slot( contact.slot, output );
+                output.push( abs( ABS_MT_TRACKING_ID, self.next_tracking_id ) );
+                self.next_tracking_id = (self.next_tracking_id + 1) & 0xFFFF;
+                output.push( abs( ABS_MT_POSITION_X, contact.x ) );
+                output.push( abs( ABS_MT_POSITION_Y, contact.y ) );
+                output.push( abs( ABS_MT_PRESSURE, contact.pressure ) );
+                self.slots.insert( contact.slot, EmittedContact {
+                    source_tracking_id: contact.tracking_id,
+                    x: contact.x,
+                    y: contact.y,
+                    pressure: contact.pressure
+                });
+                continue;
+            }
+
+            let emitted = &self.slots[ &contact.slot ];
+            let changes = [
+                (ABS_MT_POSITION_X, emitted.x, contact.x),
+                (ABS_MT_POSITION_Y, emitted.y, contact.y),
+                (ABS_MT_PRESSURE, emitted.pressure, contact.pressure)
+            ];
+
+            for &(axis, old_value, new_value) in &changes {
+                if old_value != new_value {
+                    self.select_slot( contact.slot, output );
+                    output.push( abs( axis, new_value ) );
+                }
+            }
+
+            let emitted = self.slots.get_mut( &contact.slot ).unwrap();
+            emitted.x = contact.x;
+            emitted.y = contact.y;
+            emitted.pressure = contact.pressure;
+        }
+
+        let count = self.slots.len();
+        if count != previous_count {
+            if (previous_count == 0) != (count == 0) {
+                output.push( if count > 0 { InputEventBody::KeyPress( Key::Other( BTN_TOUCH ) ) } else { InputEventBody::KeyRelease( Key::Other( BTN_TOUCH ) ) } );
+            }
+
+            let (old_tool, new_tool) = (tool_for_count( previous_count ), tool_for_count( count ));
+            if old_tool != new_tool {
+                if let Some( tool ) = old_tool {
+                    output.push( InputEventBody::KeyRelease( Key::Other( tool ) ) );
+                }
+                if let Some( tool ) = new_tool {
+                    output.push( InputEventBody::KeyPress( Key::Other( tool ) ) );
+                }
+            }
+        }
+
+        if let Some( first ) = self.slots.values().next() {
+            output.push( InputEventBody::AbsoluteMove { axis: AbsoluteAxis::X, position: first.x } );
+            output.push( InputEventBody::AbsoluteMove { axis: AbsoluteAxis::Y, position: first.y } );
+            output.push( abs( ABS_PRESSURE, first.pressure ) );
+        } else {
+            output.push( abs( ABS_PRESSURE, 0 ) );
+        }
+
+        output.push( InputEventBody::Flush );
+    }
+}
@@ -0,0 +1,369 @@
+use {
+    std::{
+        ffi::{
+            CString
+        },
+        fs,
+        io::{
+            self,
+            Write
+        },
+        os::{
+            unix::{
+                ffi::{
+                    OsStrExt
+                },
+                fs::{
+                    MetadataExt,
+                    PermissionsExt
+                }
+            }
+        },
+        path::{
+            Path,
+            PathBuf
+        }
+    },
+    parking_lot::{
+        Mutex
+    },
+    crate::{
+        config::{
+            AclEntry,
+            AclQualifier
+        }
+    }
+};
+
+// The binary format of the ACL extended attribute, as defined in `linux/posix_acl_xattr.h`.
+const ACL_XATTR_NAME: &[u8] = b"system.posix_acl_access\0";
+const ACL_XATTR_VERSION: u32 = 2;
+const ACL_XATTR_ENTRY_SIZE: usize = 8;
+const ACL_UNDEFINED_ID: u32 = !0;
+
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+fn c_path( path: &Path ) -> io::Result< CString > {
+    CString::new( path.as_os_str().as_bytes() ).map_err( |error| io::Error::new( io::ErrorKind::InvalidInput, error ) )
+}
+
+fn get_acl( path: &Path ) -> io::Result< Option< Vec< u8 > > > {
+    let c_path = c_path( path )?;
+    loop {
+        let size = unsafe { libc::getxattr( c_path.as_ptr(), ACL_XATTR_NAME.as_ptr() as *const libc::c_char, std::ptr::null_mut(), 0 ) };
+        if size < 0 {
+            let error = io::Error::last_os_error();
+            return match error.raw_os_error() {
+                Some( libc::ENODATA ) | Some( libc::EOPNOTSUPP ) => Ok( None ),
+                _ => Err( error )
+            };
+        }
+
+        let mut buffer = vec![ 0; size as usize ];
+        let size = unsafe { libc::getxattr( c_path.as_ptr(), ACL_XATTR_NAME.as_ptr() as *const libc::c_char, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len() ) };
+        if size < 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() == Some( libc::ERANGE ) {
+                // It was changed in the meantime.
+                continue;
+            }
+
+            return Err( error );
+        }
+
+        buffer.truncate( size as usize );
+        return Ok( Some( buffer ) );
+    }
+}
+
+fn set_acl( path: &Path, data: &[u8] ) -> io::Result< () > {
+    let c_path = c_path( path )?;
+    let result = unsafe { libc::setxattr( c_path.as_ptr(), ACL_XATTR_NAME.as_ptr() as *const libc::c_char, data.as_ptr() as *const libc::c_void, data.len(), 0 ) };
+    if result < 0 {
+        return Err( io::Error::last_os_error() );
+    }
+
+    Ok(())
+}
+
+fn remove_acl( path: &Path ) -> io::Result< () > {
+    let c_path = c_path( path )?;
+    let result = unsafe { libc::removexattr( c_path.as_ptr(), ACL_XATTR_NAME.as_ptr() as *const libc::c_char ) };
+    if result < 0 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some( libc::ENODATA ) {
+            return Err( error );
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the `(tag, permissions, id)` triples of an ACL.
+fn parse_acl( data: &[u8] ) -> Vec< (u16, u16, u32) > {
+    if data.len() < 4 || u32::from_le_bytes( [data[0], data[1], data[2], data[3]] ) != ACL_XATTR_VERSION {
+        return Vec::new();
+    }
+
+    data[ 4.. ].chunks_exact( ACL_XATTR_ENTRY_SIZE ).map( |chunk| {
+        let tag = u16::from_le_bytes( [chunk[0], chunk[1]] );
+        let permissions = u16::from_le_bytes( [chunk[2], chunk[3]] );
+        let id = u32::from_le_bytes( [chunk[4], chunk[5], chunk[6], chunk[7]] );
+        (tag, permissions, id)
+    }).collect()
+}
+
+/// Merges the extra entries into the node's current ACL, recalculating the mask the same way `setfacl` does.
+fn build_acl( existing: Option< &[u8] >, mode: u32, extra: &[AclEntry] ) -> Vec< u8 > {
+    let existing = existing.map( parse_acl ).unwrap_or_default();
+    let mut named: Vec< (u16, u16, u32) > = existing.iter().cloned()
+        .filter( |&(tag, _, _)| tag == ACL_USER || tag == ACL_GROUP )
+        .collect();
+
+    for entry in extra {
+        let (tag, id) = match entry.qualifier {
+            AclQualifier::User( uid ) => (ACL_USER, uid),
+            AclQualifier::Group( gid ) => (ACL_GROUP, gid)
+        };
+
+        match named.iter_mut().find( |&&mut (other_tag, _, other_id)| other_tag == tag && other_id == id ) {
+            Some( named_entry ) => named_entry.1 = entry.permissions,
+            None => named.push( (tag, entry.permissions, id) )
+        }
+    }
+
+    // With an ACL present the group bits of the mode are the mask, so the group's own permissions have to come from the ACL.
+    let group_obj = existing.iter()
+        .find( |&&(tag, _, _)| tag == ACL_GROUP_OBJ )
+        .map( |&(_, permissions, _)| permissions )
+        .unwrap_or( ((mode >> 3) & 0o7) as u16 );
+
+    let mask = named.iter().fold( group_obj, |mask, &(_, permissions, _)| mask | permissions );
+
+    let mut entries = named;
+    entries.push( (ACL_USER_OBJ, ((mode >> 6) & 0o7) as u16, ACL_UNDEFINED_ID) );
+    entries.push( (ACL_GROUP_OBJ, group_obj, ACL_UNDEFINED_ID) );
+    entries.push( (ACL_MASK, mask, ACL_UNDEFINED_ID) );
+    entries.push( (ACL_OTHER, (mode & 0o7) as u16, ACL_UNDEFINED_ID) );
+    entries.sort_by_key( |&(tag, _, id)| (tag, id) );
+
+    let mut data = Vec::with_capacity( 4 + entries.len() * ACL_XATTR_ENTRY_SIZE );
+    data.extend_from_slice( &ACL_XATTR_VERSION.to_le_bytes() );
+    for (tag, permissions, id) in entries {
+        data.extend_from_slice( &tag.to_le_bytes() );
+        data.extend_from_slice( &permissions.to_le_bytes() );
+        data.extend_from_slice( &id.to_le_bytes() );
+    }
+
+    data
+}
+
+/// Changes the mode, the ownership and the ACL of a device node; stops at the first failure.
+pub fn apply( path: &Path, chmod: Option< u16 >, owner: Option< u32 >, group: Option< u32 >, acl: &[AclEntry] ) -> Result< (), String > {
+    if owner.is_some() || group.is_some() {
+        let uid = owner.map( nix::unistd::Uid::from_raw );
+        let gid = group.map( nix::unistd::Gid::from_raw );
+        nix::unistd::chown( path, uid, gid ).map_err( |error| format!( "failed to chown {:?}: {}", path, error ) )?;
+    }
+
+    if let Some( chmod ) = chmod {
+        fs::set_permissions( path, fs::Permissions::from_mode( chmod as u32 ) ).map_err( |error| format!( "failed to chmod {:?} to {:04o}: {}", path, chmod, error ) )?;
+    }
+
+    if !acl.is_empty() {
+        let mode = fs::metadata( path ).map_err( |error| format!( "failed to stat {:?}: {}", path, error ) )?.mode();
+        let existing = get_acl( path ).map_err( |error| format!( "failed to read the ACL of {:?}: {}", path, error ) )?;
+        let data = build_acl( existing.as_deref(), mode, acl );
+        set_acl( path, &data ).map_err( |error| format!( "failed to set the ACL of {:?}: {}", path, error ) )?;
+    }
+
+    Ok(())
+}
+
+/// The mode, the ownership and the ACL of a device node from before we've changed them.
+#[derive(PartialEq, Eq)]
+pub struct OriginalPermissions {
+    path: PathBuf,
+    rdev: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    acl: Option< Vec< u8 > >
+}
+
+impl OriginalPermissions {
+    pub fn capture( path: &Path ) -> io::Result< Self > {
+        let metadata = fs::metadata( path )?;
+        Ok( OriginalPermissions {
+            path: path.to_owned(),
+            rdev: metadata.rdev(),
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            acl: get_acl( path )?
+        })
+    }
+
+    /// Checks whether the node still looks exactly like it did when this was captured.
+    pub fn is_unchanged( &self ) -> bool {
+        match OriginalPermissions::capture( &self.path ) {
+            Ok( current ) => current == *self,
+            Err( _ ) => false
+        }
+    }
+
+    pub fn restore( &self ) -> io::Result< () > {
+        let uid = nix::unistd::Uid::from_raw( self.uid );
+        let gid = nix::unistd::Gid::from_raw( self.gid );
+        nix::unistd::chown( &self.path, Some( uid ), Some( gid ) ).map_err( io::Error::other )?;
+        fs::set_permissions( &self.path, fs::Permissions::from_mode( self.mode ) )?;
+        match self.acl {
+            Some( ref acl ) => set_acl( &self.path, acl ),
+            None => remove_acl( &self.path )
+        }
+    }
+
+    fn to_journal_line( &self ) -> String {
+        let acl = match self.acl {
+            Some( ref acl ) => acl.iter().map( |byte| format!( "{:02x}", byte ) ).collect(),
+            None => "-".to_owned()
+        };
+
+        format!( "{:x} {:o} {} {} {} {}", self.rdev, self.mode, self.uid, self.gid, acl, self.path.display() )
+    }
+
+    fn from_journal_line( line: &str ) -> Option< Self > {
+        let mut parts = line.splitn( 6, ' ' );
+        let rdev = u64::from_str_radix( parts.next()?, 16 ).ok()?;
+        let mode = u32::from_str_radix( parts.next()?, 8 ).ok()?;
+        let uid = parts.next()?.parse().ok()?;
+        let gid = parts.next()?.parse().ok()?;
+        let acl = match parts.next()? {
+            "-" => None,
+            acl => {
+                if acl.len() % 2 != 0 {
+                    return None;
+                }
+
+                let bytes: Option< Vec< u8 > > = (0..acl.len()).step_by( 2 ).map( |index| u8::from_str_radix( acl.get( index..index + 2 )?, 16 ).ok() ).collect();
+                Some( bytes? )
+            }
+        };
+        let path = PathBuf::from( parts.next()? );
+
+        Some( OriginalPermissions { path, rdev, mode, uid, gid, acl } )
+    }
+}
+
+/// A file with the original permissions of every device node we've changed, so that they can be restored after a crash.
+pub struct RestoreJournal {
+    path: PathBuf,
+    /// The current entries, keyed by an ID chosen by the caller; the lock also serializes the writes.
+    entries: Mutex< Vec< (usize, String) > >
+}
+
+impl RestoreJournal {
+    pub fn new( path: impl Into< PathBuf > ) -> Self {
+        RestoreJournal {
+            path: path.into(),
+            entries: Mutex::new( Vec::new() )
+        }
+    }
+
+    /// Restores whatever was left behind by a previous run which didn't exit cleanly.
+    pub fn recover( &self ) {
+        let data = match fs::read_to_string( &self.path ) {
+            Ok( data ) => data,
+            Err( ref error ) if error.kind() == io::ErrorKind::NotFound => return,
+            Err( error ) => {
+                log::warn!( "Failed to read the permission restore journal {:?}: {}", self.path, error );
+                return;
+            }
+        };
+
+        for line in data.lines().filter( |line| !line.is_empty() ) {
+            let original = match OriginalPermissions::from_journal_line( line ) {
+                Some( original ) => original,
+                None => {
+                    log::warn!( "Invalid entry in the permission restore journal {:?}: {:?}", self.path, line );
+                    continue;
+                }
+            };
+
+            // The node could have been reused by a different device in the meantime.
+            match fs::metadata( &original.path ) {
+                Ok( metadata ) if metadata.rdev() == original.rdev => {},
+                _ => continue
+            }
+
+            log::info!( "Restoring the permissions of {:?} left over from a previous run", original.path );
+            if let Err( error ) = original.restore() {
+                log::warn!( "Failed to restore the permissions of {:?}: {}", original.path, error );
+            }
+        }
+
+        self.clear();
+    }
+
+    /// Records the original permissions of a node; this has to be done before the node is touched.
+    pub fn insert( &self, id: usize, original: &OriginalPermissions ) {
+        let mut entries = self.entries.lock();
+        entries.retain( |&(other_id, _)| other_id != id );
+        entries.push( (id, original.to_journal_line()) );
+        self.write( &entries );
+    }
+
+    /// Forgets about a node once its permissions are restored, or were never changed.
+    pub fn remove( &self, id: usize ) {
+        let mut entries = self.entries.lock();
+        let length = entries.len();
+        entries.retain( |&(other_id, _)| other_id != id );
+        if entries.len() != length {
+            self.write( &entries );
+        }
+    }
+
+    fn write( &self, entries: &[(usize, String)] ) {
+        if entries.is_empty() {
+            self.remove_file();
+            return;
+        }
+
+        let mut data = String::new();
+        for (_, line) in entries {
+            data.push_str( line );
+            data.push( '\n' );
+        }
+
+        let tmp_path = self.path.with_extension( "tmp" );
+        let result = fs::File::create( &tmp_path )
+            .and_then( |mut fp| {
+                fp.write_all( data.as_bytes() )?;
+                fp.sync_all()
+            })
+            .and_then( |_| fs::rename( &tmp_path, &self.path ) );
+
+        if let Err( error ) = result {
+            log::warn!( "Failed to update the permission restore journal {:?}: {}", self.path, error );
+        }
+    }
+
+    pub fn clear( &self ) {
+        let mut entries = self.entries.lock();
+        entries.clear();
+        self.remove_file();
+    }
+
+    fn remove_file( &self ) {
+        if let Err( error ) = fs::remove_file( &self.path ) {
+            if error.kind() != io::ErrorKind::NotFound {
+                log::warn!( "Failed to remove the permission restore journal {:?}: {}", self.path, error );
+            }
+        }
+    }
+}
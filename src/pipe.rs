@@ -0,0 +1,115 @@
+use {
+    parking_lot::{
+        Condvar,
+        Mutex
+    }
+};
+
+struct State< T > {
+    events: Vec< T >,
+    is_closed: bool
+}
+
+/// Hands the events over from a device's reader thread to its worker thread.
+pub struct EventPipe< T > {
+    state: Mutex< State< T > >,
+    condvar: Condvar
+}
+
+impl< T > EventPipe< T > {
+    pub fn new() -> Self {
+        EventPipe {
+            state: Mutex::new( State {
+                events: Vec::new(),
+                is_closed: false
+            }),
+            condvar: Condvar::new()
+        }
+    }
+
+    pub fn push( &self, event: T ) {
+        self.state.lock().events.push( event );
+        self.condvar.notify_all();
+    }
+
+    /// Closes the pipe, e.g. when the device was disconnected.
+    ///
+    /// The receiver still gets the events which are queued up.
+    pub fn close( &self ) {
+        // Done under the lock so that the receiver can't miss the wake up.
+        self.state.lock().is_closed = true;
+        self.condvar.notify_all();
+    }
+
+    /// Waits for the events and moves them into the empty `buffer`.
+    ///
+    /// Returns `false` once the pipe is closed and there's nothing left to receive.
+    pub fn receive( &self, buffer: &mut Vec< T > ) -> bool {
+        let mut state = self.state.lock();
+        loop {
+            if !state.events.is_empty() {
+                std::mem::swap( buffer, &mut state.events );
+                return true;
+            }
+
+            if state.is_closed {
+                return false;
+            }
+
+            self.condvar.wait( &mut state );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        std::{
+            sync::{
+                mpsc,
+                Arc
+            },
+            time::{
+                Duration
+            }
+        },
+        super::EventPipe
+    };
+
+    #[test]
+    fn queued_events_are_received_before_the_close() {
+        let pipe = EventPipe::new();
+        pipe.push( 1 );
+        pipe.push( 2 );
+        pipe.close();
+
+        let mut buffer = Vec::new();
+        assert!( pipe.receive( &mut buffer ) );
+        assert_eq!( buffer, [1, 2] );
+
+        buffer.clear();
+        assert!( !pipe.receive( &mut buffer ) );
+        assert!( buffer.is_empty() );
+    }
+
+    #[test]
+    fn close_wakes_up_a_waiting_receiver() {
+        let pipe = Arc::new( EventPipe::< i32 >::new() );
+        let (sender, receiver) = mpsc::channel();
+        {
+            let pipe = pipe.clone();
+            std::thread::spawn( move || {
+                let mut buffer = Vec::new();
+                while pipe.receive( &mut buffer ) {
+                    buffer.clear();
+                }
+
+                sender.send( () ).unwrap();
+            });
+        }
+
+        std::thread::sleep( Duration::from_millis( 50 ) );
+        pipe.close();
+        assert!( receiver.recv_timeout( Duration::from_secs( 5 ) ).is_ok() );
+    }
+}
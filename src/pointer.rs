@@ -0,0 +1,150 @@
+use {
+    std::{
+        sync::{
+            Arc
+        }
+    },
+    linux_input::{
+        InputEvent,
+        InputEventBody,
+        RelativeAxis
+    },
+    parking_lot::{
+        Mutex,
+        RwLock
+    },
+    crate::{
+        config::{
+            self,
+            Acceleration,
+            PointerProfile,
+            REFERENCE_DPI
+        }
+    }
+};
+
+/// Frames which are further apart than this are considered to be a separate motion.
+const MAXIMUM_FRAME_INTERVAL: f64 = 100.0;
+
+impl Acceleration {
+    /// Returns the factor by which the motion is multiplied at the given speed, in counts per millisecond.
+    pub fn factor( &self, speed: f64 ) -> f64 {
+        match *self {
+            Acceleration::Flat => 1.0,
+            Acceleration::Adaptive { threshold, slope, max_factor } => {
+                if speed <= threshold {
+                    1.0
+                } else {
+                    (1.0 + (speed - threshold) * slope).min( max_factor.max( 1.0 ) )
+                }
+            },
+            Acceleration::Custom( ref points ) => crate::axis::interpolate( points, speed )
+        }
+    }
+}
+
+/// A `[[pointer]]` entry, shared by every device which it applies to.
+pub struct Pointer {
+    pub device: String,
+    dpi: u32,
+    profile: RwLock< (String, PointerProfile) >
+}
+
+impl Pointer {
+    pub fn new( pointer: &config::Pointer, profile: &PointerProfile ) -> Self {
+        Pointer {
+            device: pointer.device.clone(),
+            dpi: pointer.dpi,
+            profile: RwLock::new( (pointer.profile.clone(), profile.clone()) )
+        }
+    }
+
+    pub fn profile_name( &self ) -> String {
+        self.profile.read().0.clone()
+    }
+
+    pub fn set_profile( &self, name: &str, profile: &PointerProfile ) {
+        log::info!( "Switching the pointer profile of '{}' to '{}'", self.device, name );
+        *self.profile.write() = (name.to_owned(), profile.clone());
+    }
+}
+
+#[derive(Default)]
+struct MotionState {
+    /// The motion received since the last flush.
+    pending: (i32, i32),
+    /// The fractional part of the motion which wasn't emitted yet.
+    remainder: (f64, f64),
+    last_frame: Option< f64 >
+}
+
+/// Applies the pointer profile to the motion of a single device.
+pub struct PointerMotion {
+    pointer: Arc< Pointer >,
+    state: Mutex< MotionState >
+}
+
+fn timestamp_in_ms( event: &InputEvent ) -> f64 {
+    event.timestamp.sec as f64 * 1000.0 + event.timestamp.usec as f64 / 1000.0
+}
+
+impl PointerMotion {
+    pub fn new( pointer: Arc< Pointer > ) -> Self {
+        PointerMotion {
+            pointer,
+            state: Mutex::new( MotionState::default() )
+        }
+    }
+
+    /// Translates the pointer motion.
+    ///
+    /// The motion is collected until the end of the frame, since the acceleration depends on the speed
+    /// in both directions. Returns `false` and leaves `output` untouched if the event isn't affected.
+    pub fn translate( &self, event: &InputEvent, output: &mut Vec< InputEvent > ) -> bool {
+        let mut state = self.state.lock();
+        match event.body {
+            InputEventBody::RelativeMove { axis: RelativeAxis::X, delta } => {
+                state.pending.0 += delta;
+                true
+            },
+            InputEventBody::RelativeMove { axis: RelativeAxis::Y, delta } => {
+                state.pending.1 += delta;
+                true
+            },
+            InputEventBody::Flush => {
+                let (dx, dy) = std::mem::take( &mut state.pending );
+                if dx == 0 && dy == 0 {
+                    return false;
+                }
+
+                let now = timestamp_in_ms( event );
+                let interval = state.last_frame.map( |last_frame| now - last_frame ).filter( |&interval| interval > 0.0 && interval < MAXIMUM_FRAME_INTERVAL );
+                state.last_frame = Some( now );
+
+                let (dpi, sensitivity, factor) = {
+                    let profile = self.pointer.profile.read();
+                    let normalization = REFERENCE_DPI as f64 / self.pointer.dpi as f64;
+                    let distance = (dx as f64).hypot( dy as f64 ) * normalization;
+                    let speed = interval.map( |interval| distance / interval ).unwrap_or( 0.0 );
+                    (normalization, profile.1.sensitivity, profile.1.acceleration.factor( speed ))
+                };
+
+                let scale = dpi * sensitivity * factor;
+                let dx = state.remainder.0 + dx as f64 * scale;
+                let dy = state.remainder.1 + dy as f64 * scale;
+                state.remainder = (dx.fract(), dy.fract());
+
+                let (dx, dy) = (dx.trunc() as i32, dy.trunc() as i32);
+                if dx != 0 {
+                    output.push( InputEvent { timestamp: event.timestamp, body: InputEventBody::RelativeMove { axis: RelativeAxis::X, delta: dx } } );
+                }
+                if dy != 0 {
+                    output.push( InputEvent { timestamp: event.timestamp, body: InputEventBody::RelativeMove { axis: RelativeAxis::Y, delta: dy } } );
+                }
+                output.push( event.clone() );
+                true
+            },
+            _ => false
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use {
+    std::{
+        collections::{
+            HashMap
+        },
+        time::{
+            Duration,
+            Instant
+        }
+    },
+    linux_input::{
+        EventKind,
+        InputEventBody,
+        Key
+    },
+    parking_lot::{
+        Condvar,
+        Mutex
+    },
+    crate::{
+        config::{
+            Remap,
+            Repeat
+        },
+        event::{
+            MSC_SCAN
+        }
+    }
+};
+
+/// A remapped key which is currently being repeated by us.
+struct HeldKey {
+    source: Key,
+    key: Key,
+    next_repeat_at: Instant
+}
+
+/// A precompiled lookup table for a single `[[remap]]` entry.
+pub struct RemapTable {
+    pub device: String,
+    pub target: String,
+    keys: HashMap< Key, Vec< Key > >,
+    generate_repeat: Option< Repeat >,
+    /// Keyed by the source device which holds the key down.
+    held: Mutex< HashMap< usize, HeldKey > >,
+    condvar: Condvar
+}
+
+impl RemapTable {
+    pub fn new( remap: &Remap ) -> Self {
+        let mut keys = HashMap::with_capacity( remap.keys.len() );
+        for (from, to) in &remap.keys {
+            keys.insert( *from, to.clone() );
+        }
+
+        RemapTable {
+            device: remap.device.clone(),
+            target: remap.target.clone(),
+            keys,
+            generate_repeat: remap.generate_repeat,
+            held: Mutex::new( HashMap::new() ),
+            condvar: Condvar::new()
+        }
+    }
+
+    pub fn generates_repeats( &self ) -> bool {
+        self.generate_repeat.is_some()
+    }
+
+    /// Whether the event is a key which this table remaps.
+    pub fn remaps( &self, body: &InputEventBody ) -> bool {
+        match *body {
+            InputEventBody::KeyPress( key ) | InputEventBody::KeyRelease( key ) => self.keys.contains_key( &key ),
+            InputEventBody::Other { kind: EventKind::Key, code, .. } => self.keys.contains_key( &Key::from( code ) ),
+            _ => false
+        }
+    }
+
+    /// Translates an event from the given source device according to this table.
+    ///
+    /// Returns `false` and leaves `output` untouched if the event isn't remapped.
+    pub fn translate( &self, source_id: usize, body: &InputEventBody, output: &mut Vec< InputEventBody > ) -> bool {
+        match *body {
+            InputEventBody::KeyPress( key ) => {
+                let keys = match self.keys.get( &key ) {
+                    Some( keys ) => keys,
+                    None => return false
+                };
+
+                output.extend( keys.iter().map( |&key| InputEventBody::KeyPress( key ) ) );
+                if let Some( ref repeat ) = self.generate_repeat {
+                    self.held.lock().insert( source_id, HeldKey {
+                        source: key,
+                        key: *keys.last().unwrap(),
+                        next_repeat_at: Instant::now() + repeat.delay
+                    });
+                    self.condvar.notify_all();
+                }
+            },
+            InputEventBody::KeyRelease( key ) => {
+                let keys = match self.keys.get( &key ) {
+                    Some( keys ) => keys,
+                    None => return false
+                };
+
+                let mut held = self.held.lock();
+                if held.get( &source_id ).map( |held| held.source == key ).unwrap_or( false ) {
+                    held.remove( &source_id );
+                }
+
+                output.extend( keys.iter().rev().map( |&key| InputEventBody::KeyRelease( key ) ) );
+            },
+            InputEventBody::Other { kind: EventKind::Key, code, value } => {
+                let keys = match self.keys.get( &Key::from( code ) ) {
+                    Some( keys ) => keys,
+                    None => return false
+                };
+
+                if self.generate_repeat.is_some() {
+                    return true;
+                }
+
+                // Only the last key of a combination is autorepeated, just as if it was held down by hand.
+                let key = *keys.last().unwrap();
+                output.push( InputEventBody::Other { kind: EventKind::Key, code: key.raw(), value } );
+            },
+            _ => return false
+        }
+
+        true
+    }
+
+    /// Waits until the held key should be repeated, and returns it.
+    ///
+    /// Returns `None` if there's nothing to repeat within the `timeout`.
+    pub fn wait_for_repeat( &self, timeout: Duration ) -> Option< Key > {
+        let period = self.generate_repeat?.period;
+        let deadline = Instant::now() + timeout;
+        let mut held = self.held.lock();
+        loop {
+            let now = Instant::now();
+            let next = held.values_mut().min_by_key( |held| held.next_repeat_at );
+            let wake_up_at = match next {
+                Some( held ) if now >= held.next_repeat_at => {
+                    held.next_repeat_at = now + period;
+                    return Some( held.key );
+                },
+                Some( held ) => held.next_repeat_at.min( deadline ),
+                None => deadline
+            };
+
+            if now >= deadline {
+                return None;
+            }
+
+            self.condvar.wait_until( &mut held, wake_up_at );
+        }
+    }
+
+    /// Stops repeating the key held on the given source device, e.g. when it was disconnected.
+    pub fn reset( &self, source_id: usize ) {
+        self.held.lock().remove( &source_id );
+    }
+}
+
+/// Whether the event is the scancode which precedes a key.
+pub fn is_scancode( body: &InputEventBody ) -> bool {
+    matches!( *body, InputEventBody::Other { kind: EventKind::Misc, code: MSC_SCAN, .. } )
+}
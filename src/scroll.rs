@@ -0,0 +1,229 @@
+use {
+    std::{
+        collections::{
+            HashMap
+        }
+    },
+    linux_input::{
+        EventKind,
+        InputEventBody,
+        Key,
+        RelativeAxis
+    },
+    parking_lot::{
+        Mutex
+    },
+    crate::{
+        config,
+        event::{
+            REL_HWHEEL,
+            REL_HWHEEL_HI_RES
+        }
+    }
+};
+
+/// The high-resolution scroll value of a single notch of the wheel.
+pub const HI_RES_PER_NOTCH: i32 = 120;
+
+/// Generates both the high-resolution and the legacy wheel events, keeping them consistent.
+#[derive(Default)]
+pub struct Wheel {
+    /// The fractional part of the high-resolution value which wasn't emitted yet.
+    remainder: f64,
+    /// The high-resolution value emitted since the last legacy event.
+    partial_notch: i32
+}
+
+impl Wheel {
+    /// Scrolls by the given number of notches, which doesn't have to be a whole number.
+    pub fn scroll( &mut self, notches: f64, axis: RelativeAxis, hi_res_axis: RelativeAxis, output: &mut Vec< InputEventBody > ) {
+        let value = self.remainder + notches * HI_RES_PER_NOTCH as f64;
+        self.remainder = value.fract();
+        self.scroll_hi_res( value.trunc() as i32, axis, hi_res_axis, output );
+    }
+
+    /// Scrolls by the given high-resolution value, emitting a legacy event for every full notch.
+    pub fn scroll_hi_res( &mut self, value: i32, axis: RelativeAxis, hi_res_axis: RelativeAxis, output: &mut Vec< InputEventBody > ) {
+        if value == 0 {
+            return;
+        }
+
+        if value.signum() != self.partial_notch.signum() {
+            self.partial_notch = 0;
+        }
+
+        output.push( InputEventBody::RelativeMove { axis: hi_res_axis, delta: value } );
+        self.partial_notch += value;
+
+        let legacy = self.partial_notch / HI_RES_PER_NOTCH;
+        if legacy != 0 {
+            self.partial_notch -= legacy * HI_RES_PER_NOTCH;
+            output.push( InputEventBody::RelativeMove { axis, delta: legacy } );
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    is_held: bool,
+    is_scrolling: bool,
+    /// The motion since the button was pressed, or since the last frame once scrolling.
+    motion: (i32, i32),
+    vertical: Wheel,
+    horizontal: Wheel
+}
+
+/// A `[[button-scroll]]` entry.
+pub struct ButtonScroll {
+    pub device: String,
+    pub target: String,
+    button: Key,
+    threshold: f64,
+    distance: f64,
+    horizontal: bool,
+    natural: bool,
+    /// Keyed by the source device, since each of them has its own button.
+    states: Mutex< HashMap< usize, State > >
+}
+
+impl ButtonScroll {
+    pub fn new( button_scroll: &config::ButtonScroll ) -> Self {
+        ButtonScroll {
+            device: button_scroll.device.clone(),
+            target: button_scroll.target.clone(),
+            button: button_scroll.button,
+            threshold: button_scroll.threshold,
+            distance: button_scroll.distance,
+            horizontal: button_scroll.horizontal,
+            natural: button_scroll.natural,
+            states: Mutex::new( HashMap::new() )
+        }
+    }
+
+    /// Feeds an event from the given source device; the generated events should be sent to the target.
+    ///
+    /// Returns `true` if the event was consumed.
+    pub fn translate( &self, source_id: usize, body: &InputEventBody, output: &mut Vec< InputEventBody > ) -> bool {
+        let mut states = self.states.lock();
+        let state = states.entry( source_id ).or_default();
+        match *body {
+            InputEventBody::KeyPress( key ) if key == self.button => {
+                *state = State {
+                    is_held: true,
+                    .. State::default()
+                };
+                true
+            },
+            InputEventBody::KeyRelease( key ) if key == self.button => {
+                if !state.is_held {
+                    return false;
+                }
+
+                // The mouse didn't move far enough, so it was meant to be a normal click.
+                if !state.is_scrolling {
+                    output.push( InputEventBody::KeyPress( self.button ) );
+                    output.push( InputEventBody::Flush );
+                    output.push( InputEventBody::KeyRelease( self.button ) );
+                    output.push( InputEventBody::Flush );
+                }
+
+                *state = State::default();
+                true
+            },
+            InputEventBody::Other { kind: EventKind::Key, code, .. } if Key::from( code ) == self.button => state.is_held,
+            InputEventBody::RelativeMove { axis: RelativeAxis::X, delta } if state.is_held => {
+                state.motion.0 += delta;
+                true
+            },
+            InputEventBody::RelativeMove { axis: RelativeAxis::Y, delta } if state.is_held => {
+                state.motion.1 += delta;
+                true
+            },
+            InputEventBody::Flush if state.is_held => {
+                if !state.is_scrolling {
+                    let (dx, dy) = state.motion;
+                    if (dx as f64).hypot( dy as f64 ) <= self.threshold {
+                        return false;
+                    }
+
+                    // Don't scroll by the distance which was needed to start scrolling.
+                    state.is_scrolling = true;
+                    state.motion = (0, 0);
+                    return false;
+                }
+
+                let (dx, dy) = std::mem::take( &mut state.motion );
+                let direction = if self.natural { 1.0 } else { -1.0 };
+                let initial_length = output.len();
+                if dy != 0 {
+                    state.vertical.scroll( dy as f64 / self.distance * direction, RelativeAxis::Wheel, RelativeAxis::WheelHiRes, output );
+                }
+                if dx != 0 && self.horizontal {
+                    state.horizontal.scroll( -dx as f64 / self.distance * direction, RelativeAxis::Other( REL_HWHEEL ), RelativeAxis::Other( REL_HWHEEL_HI_RES ), output );
+                }
+                if output.len() > initial_length {
+                    output.push( InputEventBody::Flush );
+                }
+
+                false
+            },
+            _ => false
+        }
+    }
+
+    /// Releases the button held on the given source device, e.g. when it was disconnected.
+    pub fn reset( &self, source_id: usize ) {
+        self.states.lock().remove( &source_id );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        linux_input::{
+            InputEventBody,
+            Key,
+            RelativeAxis
+        },
+        super::ButtonScroll
+    };
+
+    fn button_scroll() -> ButtonScroll {
+        ButtonScroll::new( &crate::config::ButtonScroll {
+            device: "Mouse".into(),
+            target: "Virtual Mouse".into(),
+            button: Key::MouseMiddle,
+            threshold: 0.0,
+            distance: 1.0,
+            horizontal: false,
+            natural: false
+        })
+    }
+
+    /// Moves the mouse down by a single frame and returns what was generated.
+    fn move_down( button_scroll: &ButtonScroll, source_id: usize ) -> Vec< InputEventBody > {
+        let mut output = Vec::new();
+        button_scroll.translate( source_id, &InputEventBody::RelativeMove { axis: RelativeAxis::Y, delta: 10 }, &mut output );
+        button_scroll.translate( source_id, &InputEventBody::Flush, &mut output );
+        output
+    }
+
+    #[test]
+    fn each_source_has_its_own_button() {
+        let button_scroll = button_scroll();
+        let mut output = Vec::new();
+        assert!( button_scroll.translate( 1, &InputEventBody::KeyPress( Key::MouseMiddle ), &mut output ) );
+
+        // The first frame only starts the scrolling.
+        move_down( &button_scroll, 1 );
+        assert!( !move_down( &button_scroll, 1 ).is_empty() );
+        assert!( move_down( &button_scroll, 2 ).is_empty() );
+
+        // Unplugging another mouse leaves the held button alone.
+        button_scroll.reset( 2 );
+        assert!( !move_down( &button_scroll, 1 ).is_empty() );
+
+        button_scroll.reset( 1 );
+        assert!( move_down( &button_scroll, 1 ).is_empty() );
+    }
+}
@@ -0,0 +1,410 @@
+use {
+    std::{
+        fs::{
+            self,
+            File
+        },
+        io,
+        os::{
+            unix::{
+                io::{
+                    AsRawFd
+                }
+            }
+        },
+        path::{
+            PathBuf
+        },
+        time::{
+            Duration
+        }
+    },
+    linux_input::{
+        DeviceId,
+        EventBit,
+        EventKind,
+        InputEvent,
+        InputEventBody,
+        RawInputEvent,
+        Timestamp
+    },
+    crate::{
+        force_feedback::{
+            Effect,
+            FF_GAIN
+        }
+    }
+};
+
+// `linux_input` has its own uinput wrapper, but it can only declare the keys, axes
+// and force feedback, and it doesn't let us read anything but the force feedback
+// requests from the device, so we talk to uinput ourselves.
+
+const EV_UINPUT: u16 = 0x0101;
+const UI_FF_UPLOAD: u16 = 1;
+const UI_FF_ERASE: u16 = 2;
+
+const UI_DEV_CREATE: u8 = 1;
+const UI_DEV_DESTROY: u8 = 2;
+const UI_DEV_SETUP: u8 = 3;
+const UI_ABS_SETUP: u8 = 4;
+const UI_GET_SYSNAME: u8 = 44;
+const UI_SET_EVBIT: u8 = 100;
+const UI_BEGIN_FF_UPLOAD: u8 = 200;
+const UI_END_FF_UPLOAD: u8 = 201;
+const UI_BEGIN_FF_ERASE: u8 = 202;
+const UI_END_FF_ERASE: u8 = 203;
+
+const NAME_LENGTH: usize = 80;
+
+#[repr(C)]
+struct RawDeviceId {
+    bus: u16,
+    vendor: u16,
+    product: u16,
+    version: u16
+}
+
+#[repr(C)]
+struct RawDeviceSetup {
+    id: RawDeviceId,
+    name: [u8; NAME_LENGTH],
+    force_feedback_effects_max: u32
+}
+
+#[repr(C)]
+struct RawAbsSetup {
+    axis: u16,
+    value: i32,
+    minimum: i32,
+    maximum: i32,
+    noise_threshold: i32,
+    deadzone: i32,
+    resolution: i32
+}
+
+#[repr(C)]
+struct RawForceFeedbackUpload {
+    request_id: u32,
+    return_value: i32,
+    effect: Effect,
+    old_effect: Effect
+}
+
+#[repr(C)]
+struct RawForceFeedbackErase {
+    request_id: u32,
+    return_value: i32,
+    effect_id: u32
+}
+
+/// Returns the `UI_SET_*BIT` request number for the given event kind.
+fn set_bit_request( kind: EventKind ) -> Option< u8 > {
+    let request = match kind {
+        EventKind::Key => 101,
+        EventKind::RelativeAxis => 102,
+        EventKind::AbsoluteAxis => 103,
+        EventKind::Misc => 104,
+        EventKind::LED => 105,
+        EventKind::Sound => 106,
+        EventKind::ForceFeedback => 107,
+        EventKind::Switch => 109,
+        _ => return None
+    };
+
+    Some( request )
+}
+
+fn check( result: libc::c_int ) -> Result< libc::c_int, io::Error > {
+    if result < 0 {
+        Err( io::Error::last_os_error() )
+    } else {
+        Ok( result )
+    }
+}
+
+pub struct ForceFeedbackUpload< 'a > {
+    device: &'a VirtualDevice,
+    raw: RawForceFeedbackUpload,
+    is_finished: bool
+}
+
+impl< 'a > ForceFeedbackUpload< 'a > {
+    pub fn effect_id( &self ) -> u16 {
+        self.raw.effect.id as u16
+    }
+
+    pub fn effect( &self ) -> Effect {
+        self.raw.effect
+    }
+
+    pub fn complete( mut self ) -> Result< (), io::Error > {
+        self.finish()
+    }
+
+    fn finish( &mut self ) -> Result< (), io::Error > {
+        if std::mem::replace( &mut self.is_finished, true ) {
+            return Ok(());
+        }
+
+        let request = nix::request_code_write!( b'U', UI_END_FF_UPLOAD, std::mem::size_of::< RawForceFeedbackUpload >() );
+        check( unsafe { libc::ioctl( self.device.fp.as_raw_fd(), request as _, &self.raw as *const RawForceFeedbackUpload ) } )?;
+        Ok(())
+    }
+}
+
+// The process which uploads the effect is blocked until the request is completed.
+impl< 'a > Drop for ForceFeedbackUpload< 'a > {
+    fn drop( &mut self ) {
+        let _ = self.finish();
+    }
+}
+
+pub struct ForceFeedbackErase< 'a > {
+    device: &'a VirtualDevice,
+    raw: RawForceFeedbackErase,
+    is_finished: bool
+}
+
+impl< 'a > ForceFeedbackErase< 'a > {
+    pub fn effect_id( &self ) -> u16 {
+        self.raw.effect_id as u16
+    }
+
+    pub fn complete( mut self ) -> Result< (), io::Error > {
+        self.finish()
+    }
+
+    fn finish( &mut self ) -> Result< (), io::Error > {
+        if std::mem::replace( &mut self.is_finished, true ) {
+            return Ok(());
+        }
+
+        let request = nix::request_code_write!( b'U', UI_END_FF_ERASE, std::mem::size_of::< RawForceFeedbackErase >() );
+        check( unsafe { libc::ioctl( self.device.fp.as_raw_fd(), request as _, &self.raw as *const RawForceFeedbackErase ) } )?;
+        Ok(())
+    }
+}
+
+impl< 'a > Drop for ForceFeedbackErase< 'a > {
+    fn drop( &mut self ) {
+        let _ = self.finish();
+    }
+}
+
+/// An event written into the virtual device by somebody else.
+pub enum DeviceRequest< 'a > {
+    Upload( ForceFeedbackUpload< 'a > ),
+    Erase( ForceFeedbackErase< 'a > ),
+    Enable {
+        effect_id: u16,
+        cycle_count: i32
+    },
+    Disable {
+        effect_id: u16
+    },
+    /// The gain, the autocenter or some other force feedback setting.
+    Other {
+        code: u16,
+        value: i32
+    },
+    Led {
+        code: u16,
+        value: i32
+    }
+}
+
+pub struct VirtualDevice {
+    fp: File
+}
+
+impl VirtualDevice {
+    /// Creates a new device; `extra_bits` are the codes of the events which `EventBit` can't describe, e.g. the LEDs.
+    pub fn create( id: DeviceId, name: &str, event_bits: &[EventBit], extra_bits: &[(EventKind, u16)] ) -> Result< Self, io::Error > {
+        if name.len() >= NAME_LENGTH {
+            return Err( io::Error::new( io::ErrorKind::InvalidInput, "the name of the device is too long" ) );
+        }
+
+        let fp = fs::OpenOptions::new()
+            .read( true )
+            .write( true )
+            .open( "/dev/uinput" )?;
+
+        let device = VirtualDevice { fp };
+        let mut abs_setups = Vec::new();
+        let mut bits = Vec::new();
+        for bit in event_bits {
+            match *bit {
+                EventBit::Key( key ) => bits.push( (EventKind::Key, key.raw()) ),
+                EventBit::RelativeAxis( axis ) => bits.push( (EventKind::RelativeAxis, axis.raw()) ),
+                EventBit::AbsoluteAxis( ref bit ) => {
+                    bits.push( (EventKind::AbsoluteAxis, bit.axis.raw()) );
+                    abs_setups.push( RawAbsSetup {
+                        axis: bit.axis.raw(),
+                        value: (bit.maximum - bit.minimum) / 2 + bit.minimum,
+                        minimum: bit.minimum,
+                        maximum: bit.maximum,
+                        noise_threshold: bit.noise_threshold,
+                        deadzone: bit.deadzone,
+                        resolution: bit.resolution
+                    });
+                },
+                EventBit::ForceFeedback( bit ) => bits.push( (EventKind::ForceFeedback, bit.raw()) )
+            }
+        }
+        bits.extend_from_slice( extra_bits );
+
+        let mut kinds: Vec< EventKind > = Vec::new();
+        for &(kind, code) in &bits {
+            if !kinds.contains( &kind ) {
+                kinds.push( kind );
+                device.ioctl_int( UI_SET_EVBIT, kind.raw() as _ )?;
+            }
+
+            // Some kinds, e.g. the autorepeat, have no codes to declare.
+            if let Some( request ) = set_bit_request( kind ) {
+                device.ioctl_int( request, code as _ )?;
+            }
+        }
+
+        for abs_setup in &abs_setups {
+            let request = nix::request_code_write!( b'U', UI_ABS_SETUP, std::mem::size_of::< RawAbsSetup >() );
+            check( unsafe { libc::ioctl( device.fp.as_raw_fd(), request as _, abs_setup as *const RawAbsSetup ) } )?;
+        }
+
+        let has_force_feedback = kinds.contains( &EventKind::ForceFeedback );
+        let mut setup = RawDeviceSetup {
+            id: RawDeviceId {
+                bus: id.bus.raw(),
+                vendor: id.vendor,
+                product: id.product,
+                version: id.version
+            },
+            name: [0; NAME_LENGTH],
+            force_feedback_effects_max: if has_force_feedback { 1 } else { 0 }
+        };
+        setup.name[ ..name.len() ].copy_from_slice( name.as_bytes() );
+
+        let request = nix::request_code_write!( b'U', UI_DEV_SETUP, std::mem::size_of::< RawDeviceSetup >() );
+        check( unsafe { libc::ioctl( device.fp.as_raw_fd(), request as _, &setup as *const RawDeviceSetup ) } )?;
+
+        let request = nix::request_code_none!( b'U', UI_DEV_CREATE );
+        check( unsafe { libc::ioctl( device.fp.as_raw_fd(), request as _ ) } )?;
+
+        Ok( device )
+    }
+
+    fn ioctl_int( &self, nr: u8, value: libc::c_int ) -> Result< (), io::Error > {
+        let request = nix::request_code_write!( b'U', nr, std::mem::size_of::< libc::c_int >() );
+        check( unsafe { libc::ioctl( self.fp.as_raw_fd(), request as _, value ) } )?;
+        Ok(())
+    }
+
+    fn sysname( &self ) -> Result< String, io::Error > {
+        let mut buffer = [0_u8; 64];
+        let request = nix::request_code_read!( b'U', UI_GET_SYSNAME, buffer.len() );
+        check( unsafe { libc::ioctl( self.fp.as_raw_fd(), request as _, buffer.as_mut_ptr() ) } )?;
+
+        let length = buffer.iter().position( |&byte| byte == 0 ).unwrap_or( buffer.len() );
+        Ok( String::from_utf8_lossy( &buffer[ ..length ] ).into_owned() )
+    }
+
+    /// Returns the path of the device's event node.
+    pub fn path( &self ) -> Result< PathBuf, io::Error > {
+        let sysname = self.sysname()?;
+        for entry in fs::read_dir( format!( "/sys/devices/virtual/input/{}", sysname ) )? {
+            let file_name = entry?.file_name();
+            if file_name.to_string_lossy().starts_with( "event" ) {
+                return Ok( PathBuf::from( "/dev/input" ).join( file_name ) );
+            }
+        }
+
+        Err( io::Error::new( io::ErrorKind::NotFound, format!( "no event node found for '{}'", sysname ) ) )
+    }
+
+    /// Emits an event into the device; nothing is sent until a `Flush` is emitted.
+    pub fn emit< T >( &self, body: T ) -> Result< (), io::Error > where T: AsRef< InputEventBody > {
+        let raw_event: RawInputEvent = InputEvent {
+            timestamp: Timestamp { sec: 0, usec: 0 },
+            body: body.as_ref().clone()
+        }.into();
+
+        let size = std::mem::size_of::< RawInputEvent >();
+        let result = unsafe { libc::write( self.fp.as_raw_fd(), &raw_event as *const RawInputEvent as *const libc::c_void, size ) };
+        if result < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        Ok(())
+    }
+
+    /// Sets the delay and the period of the kernel's autorepeat; the device must have been created with `EV_REP`.
+    pub fn set_autorepeat( &self, delay: Duration, period: Duration ) -> Result< (), io::Error > {
+        const REP_DELAY: u16 = 0x00;
+        const REP_PERIOD: u16 = 0x01;
+
+        self.emit( InputEventBody::Other { kind: EventKind::AutoRepeat, code: REP_DELAY, value: delay.as_millis() as i32 } )?;
+        self.emit( InputEventBody::Other { kind: EventKind::AutoRepeat, code: REP_PERIOD, value: period.as_millis() as i32 } )?;
+        self.emit( InputEventBody::Flush )
+    }
+
+    /// Waits for an event written into the device by somebody else.
+    ///
+    /// Returns `None` on a timeout or for events which aren't of interest.
+    pub fn poll( &self, timeout: Option< Duration > ) -> Result< Option< DeviceRequest< '_ > >, io::Error > {
+        if !linux_input::poll_read( self.fp.as_raw_fd(), timeout )? {
+            return Ok( None );
+        }
+
+        let mut event = RawInputEvent::default();
+        let size = std::mem::size_of::< RawInputEvent >();
+        let result = unsafe { libc::read( self.fp.as_raw_fd(), &mut event as *mut RawInputEvent as *mut libc::c_void, size ) };
+        if result < 0 {
+            return Err( io::Error::last_os_error() );
+        } else if result as usize != size {
+            return Ok( None );
+        }
+
+        let request = match (event.kind, event.code) {
+            (EV_UINPUT, UI_FF_UPLOAD) => {
+                let mut raw: RawForceFeedbackUpload = unsafe { std::mem::zeroed() };
+                raw.request_id = event.value as u32;
+                let request = nix::request_code_readwrite!( b'U', UI_BEGIN_FF_UPLOAD, std::mem::size_of::< RawForceFeedbackUpload >() );
+                check( unsafe { libc::ioctl( self.fp.as_raw_fd(), request as _, &mut raw as *mut RawForceFeedbackUpload ) } )?;
+                DeviceRequest::Upload( ForceFeedbackUpload { device: self, raw, is_finished: false } )
+            },
+            (EV_UINPUT, UI_FF_ERASE) => {
+                let mut raw = RawForceFeedbackErase {
+                    request_id: event.value as u32,
+                    return_value: 0,
+                    effect_id: 0
+                };
+                let request = nix::request_code_readwrite!( b'U', UI_BEGIN_FF_ERASE, std::mem::size_of::< RawForceFeedbackErase >() );
+                check( unsafe { libc::ioctl( self.fp.as_raw_fd(), request as _, &mut raw as *mut RawForceFeedbackErase ) } )?;
+                DeviceRequest::Erase( ForceFeedbackErase { device: self, raw, is_finished: false } )
+            },
+            (kind, code) if kind == EventKind::ForceFeedback.raw() => {
+                if code >= FF_GAIN {
+                    DeviceRequest::Other { code, value: event.value }
+                } else if event.value > 0 {
+                    DeviceRequest::Enable { effect_id: code, cycle_count: event.value }
+                } else {
+                    DeviceRequest::Disable { effect_id: code }
+                }
+            },
+            (kind, code) if kind == EventKind::LED.raw() => DeviceRequest::Led { code, value: event.value },
+            _ => return Ok( None )
+        };
+
+        Ok( Some( request ) )
+    }
+}
+
+impl Drop for VirtualDevice {
+    fn drop( &mut self ) {
+        let request = nix::request_code_none!( b'U', UI_DEV_DESTROY );
+        unsafe {
+            libc::ioctl( self.fp.as_raw_fd(), request as _ );
+        }
+    }
+}